@@ -0,0 +1,305 @@
+use crate::dns::create_request;
+use crate::remote::Session;
+use crate::{Config, DohError, DohResult};
+
+use dns_message_parser::{Class, DomainError, DomainName, QClass, QType, Question};
+
+use futures::future::join_all;
+use futures::lock::Mutex;
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::time::timeout as create_timeout;
+
+/// The length of the random labels in bytes, which are encoded as hex.
+const RANDOM_LABEL_LENGTH: usize = 8;
+
+enum NamesInner {
+    List(Vec<DomainName>),
+    Random(String),
+}
+
+/// The domain names, which are queried by `doh-client bench`.
+pub struct Names(NamesInner);
+
+impl Names {
+    /// The domain names `domain_names` are queried one after the other and again from the
+    /// beginning. The list must not be empty.
+    pub fn list(domain_names: Vec<DomainName>) -> Names {
+        assert!(
+            !domain_names.is_empty(),
+            "The list of domain names is empty"
+        );
+        Names(NamesInner::List(domain_names))
+    }
+
+    /// Random subdomains of `suffix` are queried, so that the responses are not cached by the
+    /// server.
+    pub fn random(suffix: &str) -> Result<Names, DomainError> {
+        let suffix = suffix.trim_end_matches('.');
+        // Check that the domain names are valid with a random label.
+        let label = "0".repeat(RANDOM_LABEL_LENGTH * 2);
+        DomainName::try_from(format!("{}.{}", label, suffix).as_str())?;
+        Ok(Names(NamesInner::Random(suffix.to_string())))
+    }
+
+    fn get(&self, i: usize) -> DohResult<DomainName> {
+        match &self.0 {
+            NamesInner::List(domain_names) => Ok(domain_names[i % domain_names.len()].clone()),
+            NamesInner::Random(suffix) => {
+                let mut label = [0; RANDOM_LABEL_LENGTH];
+                if SystemRandom::new().fill(&mut label).is_err() {
+                    return Err(DohError::Random);
+                }
+                let label: String = label.iter().map(|b| format!("{:02x}", b)).collect();
+                let domain_name = format!("{}.{}", label, suffix);
+                Ok(DomainName::try_from(domain_name.as_str()).expect("Checked by Names::random"))
+            }
+        }
+    }
+}
+
+/// The result of a benchmark.
+struct Report {
+    upstream: String,
+    concurrency: usize,
+    elapsed: Duration,
+    /// The latencies of the successful requests, in ascending order.
+    latencies: Vec<Duration>,
+    rcodes: BTreeMap<String, usize>,
+    errors: BTreeMap<&'static str, usize>,
+    connections: u32,
+}
+
+impl Report {
+    fn requests(&self) -> usize {
+        self.latencies.len() + self.errors.values().sum::<usize>()
+    }
+
+    /// Get the latency, which is greater than or equal to `p` percent of the latencies.
+    fn percentile(&self, p: usize) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let i = (self.latencies.len() * p).div_ceil(100);
+        Some(self.latencies[i.max(1) - 1])
+    }
+}
+
+fn write_map<K: Display>(f: &mut Formatter<'_>, map: &BTreeMap<K, usize>) -> FmtResult {
+    if map.is_empty() {
+        return writeln!(f, "none");
+    }
+    let items: Vec<String> = map.iter().map(|(k, n)| format!("{} {}", k, n)).collect();
+    writeln!(f, "{}", items.join(", "))
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let requests = self.requests();
+        let elapsed = self.elapsed.as_secs_f64();
+        writeln!(f, "Upstream: {}", self.upstream)?;
+        writeln!(
+            f,
+            "Requests: {} (concurrency {})",
+            requests, self.concurrency
+        )?;
+        writeln!(f, "Time: {:.3} s", elapsed)?;
+        writeln!(f, "QPS: {:.1}", requests as f64 / elapsed)?;
+        write!(f, "Latency:")?;
+        if self.latencies.is_empty() {
+            write!(f, " none")?;
+        }
+        for (name, p) in &[("p50", 50), ("p90", 90), ("p99", 99), ("max", 100)] {
+            if let Some(latency) = self.percentile(*p) {
+                write!(f, " {} {:.1} ms", name, latency.as_secs_f64() * 1000.0)?;
+            }
+        }
+        writeln!(f)?;
+        write!(f, "Responses: ")?;
+        write_map(f, &self.rcodes)?;
+        write!(f, "Errors: ")?;
+        write_map(f, &self.errors)?;
+        writeln!(
+            f,
+            "Connections: {} ({} reconnects)",
+            self.connections,
+            self.connections.saturating_sub(1)
+        )
+    }
+}
+
+/// The result of a single request, which is the latency and the RCODE or the error.
+type Sample = (Duration, Result<String, &'static str>);
+
+async fn request(session: &Mutex<Session>, question: Question) -> Sample {
+    let mut dns_request = create_request(question);
+    let start = Instant::now();
+    let resolve = session.lock().await.resolve();
+    resolve.await;
+    let mut guard_session = session.lock().await;
+    let timeout = guard_session.timeout();
+    let result = guard_session.start_request(&mut dns_request).await;
+    drop(guard_session);
+    let (response, connection_id) = match result {
+        Ok(response) => response,
+        Err(e) => return (start.elapsed(), Err(e.kind())),
+    };
+    let result = match create_timeout(timeout, response).await {
+        Ok(Ok((dns_response, _))) => {
+            return (
+                start.elapsed(),
+                Ok(format!("{:?}", dns_response.flags.rcode)),
+            );
+        }
+        Ok(Err(e)) => e.kind(),
+        Err(_) => DohError::Timeout.kind(),
+    };
+    let elapsed = start.elapsed();
+    session.lock().await.disconnect(connection_id);
+    (elapsed, Err(result))
+}
+
+async fn worker(
+    session: &Mutex<Session>,
+    names: &Names,
+    qtype: &QType,
+    next: &AtomicUsize,
+    requests: usize,
+) -> DohResult<Vec<Sample>> {
+    let mut samples = Vec::new();
+    loop {
+        let i = next.fetch_add(1, Ordering::Relaxed);
+        if i >= requests {
+            return Ok(samples);
+        }
+        let domain_name = names.get(i)?;
+        let question = Question::new(domain_name, QClass::Class(Class::IN), qtype.clone());
+        samples.push(request(session, question).await);
+    }
+}
+
+async fn run(
+    session: Session,
+    names: &Names,
+    qtype: &QType,
+    requests: usize,
+    concurrency: usize,
+) -> DohResult<Report> {
+    let upstream = session.to_string();
+    let session = Mutex::new(session);
+    let next = AtomicUsize::new(0);
+
+    let start = Instant::now();
+    let workers = (0..concurrency).map(|_| worker(&session, names, qtype, &next, requests));
+    let results = join_all(workers).await;
+    let elapsed = start.elapsed();
+
+    let mut latencies = Vec::with_capacity(requests);
+    let mut rcodes = BTreeMap::new();
+    let mut errors = BTreeMap::new();
+    for result in results {
+        for (latency, result) in result? {
+            match result {
+                Ok(rcode) => {
+                    latencies.push(latency);
+                    *rcodes.entry(rcode).or_insert(0) += 1;
+                }
+                Err(kind) => *errors.entry(kind).or_insert(0) += 1,
+            }
+        }
+    }
+    latencies.sort();
+    let connections = session.into_inner().connection_id();
+    Ok(Report {
+        upstream,
+        concurrency,
+        elapsed,
+        latencies,
+        rcodes,
+        errors,
+        connections,
+    })
+}
+
+/// Send `requests` DNS requests with the type `qtype` for the domain names `names` to the DoH
+/// server of `config`, where `concurrency` requests are in flight at the same time.
+///
+/// Returns a report with the queries per second, the latency percentiles, the errors grouped by
+/// their kind and the number of reconnects.
+pub async fn bench(
+    config: Config,
+    names: Names,
+    qtype: QType,
+    requests: usize,
+    concurrency: usize,
+) -> DohResult<String> {
+    let session = config.into_remote_session();
+    let report = run(session, &names, &qtype, requests, concurrency.max(1)).await?;
+    Ok(report.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use dns_message_parser::{DomainName, QType, Type};
+
+    use std::convert::TryFrom;
+
+    use super::{run, Names};
+    use crate::remote::mock::{session_config, start};
+    use crate::remote::{Host, Session};
+
+    async fn create_session() -> Session {
+        let remote_addr = start().await;
+        Session::new(Host::Direct(vec![remote_addr]), session_config(), None)
+    }
+
+    #[test]
+    fn test_names() {
+        let domain_name = DomainName::try_from("example.com").unwrap();
+        let names = Names::list(vec![domain_name.clone()]);
+        assert_eq!(names.get(3).unwrap(), domain_name);
+
+        let names = Names::random("example.com").unwrap();
+        let a = names.get(0).unwrap().to_string();
+        let b = names.get(0).unwrap().to_string();
+        assert!(a.ends_with(".example.com."));
+        assert_ne!(a, b);
+
+        assert!(Names::random(&"a.".repeat(120)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bench() {
+        let names = Names::random("example.com.").unwrap();
+        let qtype = QType::Type(Type::A);
+        let report = run(create_session().await, &names, &qtype, 50, 5)
+            .await
+            .unwrap();
+        assert_eq!(report.requests(), 50);
+        assert_eq!(report.latencies.len(), 50);
+        assert_eq!(report.rcodes.get("NoError"), Some(&50));
+        assert!(report.errors.is_empty());
+        assert_eq!(report.connections, 1);
+        assert!(report.percentile(50) <= report.percentile(99));
+        assert_eq!(report.percentile(100), report.latencies.last().cloned());
+    }
+
+    #[tokio::test]
+    async fn test_bench_error() {
+        let mut session = create_session().await;
+        session.switch(vec!["127.0.0.1:1".parse().unwrap()]);
+        let names = Names::list(vec![DomainName::try_from("example.com").unwrap()]);
+        let qtype = QType::Type(Type::A);
+        let report = run(session, &names, &qtype, 2, 1).await.unwrap();
+        assert_eq!(report.requests(), 2);
+        assert_eq!(report.latencies.len(), 0);
+        assert!(!report.errors.is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{DohError, DohResult};
+
+use rustls::internal::pemfile::certs;
+use rustls::RootCertStore;
+
+use std::fs::{read_dir, File};
+use std::io::BufReader;
+use std::path::PathBuf;
+
+/// The extensions of the files, which are loaded from a CA directory.
+const EXTENSIONS: [&str; 2] = ["pem", "crt"];
+
+/// Add all certificates of the pem file `path` to `root_store`.
+///
+/// Returns the number of certificates. Fails if the file does not contain any certificate or if
+/// one of them is invalid.
+fn add_file(root_store: &mut RootCertStore, path: &str) -> DohResult<usize> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => return Err(DohError::File(path.to_string(), e)),
+    };
+    let cert_chain = match certs(&mut BufReader::new(file)) {
+        Ok(cert_chain) if !cert_chain.is_empty() => cert_chain,
+        _ => return Err(DohError::PEMParser(path.to_string())),
+    };
+    for (i, cert) in cert_chain.iter().enumerate() {
+        if let Err(e) = root_store.add(cert) {
+            return Err(DohError::Certificate(path.to_string(), i + 1, e));
+        }
+    }
+    Ok(cert_chain.len())
+}
+
+/// Add all certificates of the pem files (`*.pem` and `*.crt`) in the directory `path` to
+/// `root_store`.
+fn add_dir(root_store: &mut RootCertStore, path: &str) -> DohResult<usize> {
+    let entries = match read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) => return Err(DohError::File(path.to_string(), e)),
+    };
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if path.is_file() && EXTENSIONS.iter().any(|e| Some(*e) == extension) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    let mut n = 0;
+    for path in paths {
+        n += add_file(root_store, &path.to_string_lossy())?;
+    }
+    if n == 0 {
+        return Err(DohError::NoCaCertificates);
+    }
+    Ok(n)
+}
+
+#[cfg(not(feature = "bundled-roots"))]
+fn add_default(root_store: &mut RootCertStore) -> DohResult<()> {
+    let native_store = match rustls_native_certs::load_native_certs() {
+        Ok(native_store) => native_store,
+        Err((Some(native_store), e)) => {
+            warn!(
+                "Could not load all certificates of the system trust store: {}",
+                e
+            );
+            native_store
+        }
+        Err((None, e)) => return Err(e.into()),
+    };
+    if native_store.is_empty() {
+        return Err(DohError::NoCaCertificates);
+    }
+    info!(
+        "Loaded {} certificates of the system trust store",
+        native_store.len()
+    );
+    root_store.roots.extend(native_store.roots);
+    Ok(())
+}
+
+#[cfg(feature = "bundled-roots")]
+fn add_default(root_store: &mut RootCertStore) -> DohResult<()> {
+    root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    info!("Loaded {} bundled certificates", root_store.len());
+    Ok(())
+}
+
+/// Create the store of the trusted CA certificates of the pem files `cafiles` and the
+/// directories `cadirs`.
+///
+/// If neither files nor directories are given, then the system trust store is used (or the
+/// bundled Mozilla root certificates if the feature `bundled-roots` is enabled).
+pub(crate) fn create_root_store(cafiles: &[&str], cadirs: &[&str]) -> DohResult<RootCertStore> {
+    let mut root_store = RootCertStore::empty();
+    if cafiles.is_empty() && cadirs.is_empty() {
+        add_default(&mut root_store)?;
+        return Ok(root_store);
+    }
+    for cafile in cafiles {
+        let n = add_file(&mut root_store, cafile)?;
+        debug!("Loaded {} certificates of {}", n, cafile);
+    }
+    for cadir in cadirs {
+        let n = add_dir(&mut root_store, cadir)?;
+        debug!("Loaded {} certificates of {}", n, cadir);
+    }
+    Ok(root_store)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+    use std::process::id;
+
+    use super::create_root_store;
+    use crate::DohError;
+
+    const CA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs/ca.pem");
+    const SERVER_CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs/server.pem");
+    const CERTS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs");
+    const INVALID_CERT: &str = "-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n";
+
+    #[test]
+    fn test_cafiles() {
+        let root_store = create_root_store(&[CA], &[]).unwrap();
+        assert_eq!(root_store.len(), 1);
+        let root_store = create_root_store(&[CA, SERVER_CERT], &[]).unwrap();
+        assert_eq!(root_store.len(), 2);
+        let root_store = create_root_store(&[], &[CERTS]).unwrap();
+        assert_eq!(root_store.len(), 4);
+    }
+
+    #[test]
+    fn test_cafiles_error() {
+        let dir = temp_dir().join(format!("doh-client-test-{}-ca", id()));
+        create_dir_all(&dir).unwrap();
+        let empty = dir.join("empty.pem");
+        write(&empty, "").unwrap();
+        let invalid = dir.join("invalid.pem");
+        let content = read_to_string(CA).unwrap();
+        write(&invalid, format!("{}{}", content, INVALID_CERT)).unwrap();
+
+        let empty = empty.to_str().unwrap();
+        match create_root_store(&[empty], &[]) {
+            Err(DohError::PEMParser(path)) => assert_eq!(path, empty),
+            _ => panic!("Empty pem file is accepted"),
+        }
+        let invalid = invalid.to_str().unwrap();
+        match create_root_store(&[CA, invalid], &[]) {
+            Err(DohError::Certificate(path, i, _)) => {
+                assert_eq!(path, invalid);
+                assert_eq!(i, 2);
+            }
+            _ => panic!("Invalid certificate is accepted"),
+        }
+        assert!(create_root_store(&[], &[dir.to_str().unwrap()]).is_err());
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
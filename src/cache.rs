@@ -1,94 +1,318 @@
 use std::hash::Hash;
+use std::mem::size_of;
 use std::time::{Duration, Instant};
 
 use lru::LruCache;
 
+/// The number of hits, after that an entry is popular enough to be prefetched.
+const PREFETCH_MIN_HITS: u32 = 3;
+
+struct Entry<V> {
+    value: V,
+    expiry: Instant,
+    ttl: Duration,
+    hits: u32,
+    prefetching: bool,
+    /// The estimated memory usage of the entry in bytes.
+    weight: usize,
+}
+
+/// The statistics of a cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct Stats {
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+    pub(crate) evictions: u64,
+}
+
+impl Stats {
+    pub(crate) fn add(&mut self, other: &Stats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.evictions += other.evictions;
+    }
+
+    /// Get the ratio of the lookups, which were answered by the cache.
+    pub(crate) fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
 pub(crate) struct Cache<K: Eq + Hash, V> {
-    lru_cache: LruCache<K, (V, Instant)>,
+    lru_cache: LruCache<K, Entry<V>>,
+    max_size: usize,
+    max_bytes: Option<usize>,
+    bytes: usize,
+    prefetch: u32,
+    stats: Stats,
 }
 
 impl<K: Eq + Hash + Clone, V> Cache<K, V> {
-    pub(crate) fn new(max_size: usize) -> Cache<K, V> {
+    /// Create a new cache with `max_size` entries, which use at most `max_bytes` bytes, if it is
+    /// set.
+    ///
+    /// Popular entries are prefetched, if they are requested within the last `prefetch` percent
+    /// of their TTL. If `prefetch` is 0, then no entries are prefetched.
+    pub(crate) fn new(max_size: usize, max_bytes: Option<usize>, prefetch: u32) -> Cache<K, V> {
         Cache {
-            lru_cache: LruCache::new(max_size),
+            lru_cache: LruCache::unbounded(),
+            max_size,
+            max_bytes,
+            bytes: 0,
+            prefetch,
+            stats: Stats::default(),
         }
     }
 
+    /// Get an entry, which is not expired. An expired entry is removed.
     pub(crate) fn get(&mut self, k: &K) -> Option<&mut V> {
-        if let Some(v) = self.lru_cache.pop(k) {
-            if v.1 > Instant::now() {
-                self.lru_cache.put(k.clone(), v);
-                return Some(&mut self.lru_cache.peek_mut(k).unwrap().0);
+        match self.lru_cache.peek(k) {
+            Some(v) if v.expiry > Instant::now() => {}
+            Some(_) => {
+                self.remove(k);
+                self.stats.misses += 1;
+                return None;
+            }
+            None => {
+                self.stats.misses += 1;
+                return None;
             }
         }
-        None
+        self.stats.hits += 1;
+        let v = self.lru_cache.get_mut(k)?;
+        v.hits = v.hits.saturating_add(1);
+        Some(&mut v.value)
     }
 
     pub(crate) fn get_expired(&mut self, k: &K) -> Option<&mut V> {
         if let Some(v) = self.lru_cache.get_mut(k) {
-            if v.1 > Instant::now() {
-                return Some(&mut v.0);
+            if v.expiry > Instant::now() {
+                self.stats.hits += 1;
+                v.hits = v.hits.saturating_add(1);
+                return Some(&mut v.value);
             }
         }
+        self.stats.misses += 1;
         None
     }
 
-    pub(crate) fn get_expired_fallback(&mut self, k: &K) -> Option<&mut V> {
+    /// Get an entry, even if it is expired, but only if it expired less than `max_age` ago.
+    pub(crate) fn get_stale(&mut self, k: &K, max_age: Duration) -> Option<&mut V> {
         if let Some(v) = self.lru_cache.get_mut(k) {
-            return Some(&mut v.0);
+            if v.expiry + max_age > Instant::now() {
+                return Some(&mut v.value);
+            }
         }
         None
     }
 
-    pub(crate) fn put(&mut self, k: K, v: V, d: Duration) {
-        self.lru_cache.put(k, (v, Instant::now() + d));
+    /// Get the time since the entry was put into the cache.
+    ///
+    /// The age is calculated from the remaining time of the entry instead of `expiry - ttl`, which
+    /// can be before the origin of `Instant` for an entry, which was loaded from the cache file.
+    pub(crate) fn age(&self, k: &K) -> Option<Duration> {
+        let v = self.lru_cache.peek(k)?;
+        let now = Instant::now();
+        if now < v.expiry {
+            Some(v.ttl.saturating_sub(v.expiry - now))
+        } else {
+            Some(v.ttl.saturating_add(now - v.expiry))
+        }
+    }
+
+    /// Check if the entry should be refreshed before it expires.
+    ///
+    /// This is the case if the entry was requested often and is in the last percents of its TTL.
+    /// Returns `true` only once per entry, so that only one refresh is started.
+    pub(crate) fn prefetch(&mut self, k: &K) -> bool {
+        if self.prefetch == 0 {
+            return false;
+        }
+        if let Some(v) = self.lru_cache.peek_mut(k) {
+            let now = Instant::now();
+            if v.prefetching || v.hits < PREFETCH_MIN_HITS || v.expiry <= now {
+                return false;
+            }
+            if v.expiry - now <= v.ttl * self.prefetch / 100 {
+                v.prefetching = true;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Allow a new refresh of the entry, after the refresh, which was started by `prefetch`,
+    /// failed or its response was not cached.
+    pub(crate) fn reset_prefetch(&mut self, k: &K) {
+        if let Some(v) = self.lru_cache.peek_mut(k) {
+            v.prefetching = false;
+        }
+    }
+
+    /// Put an entry, where `size` is the size of the value in bytes (for example the size of
+    /// the encoded response).
+    pub(crate) fn put(&mut self, k: K, v: V, size: usize, d: Duration) {
+        self.put_with_expiry(k, v, size, Instant::now() + d, d);
+    }
+
+    /// Put an entry, which expires at `expiry` and had the TTL `ttl` when it was received.
+    ///
+    /// The least recently used entries are evicted, until the number of entries and the bytes
+    /// are within the limits. An entry, which is larger than the maximum bytes on its own, is not
+    /// put, so that it does not evict all other entries.
+    pub(crate) fn put_with_expiry(
+        &mut self,
+        k: K,
+        v: V,
+        size: usize,
+        expiry: Instant,
+        ttl: Duration,
+    ) {
+        // The key and the entry itself are part of the estimated memory usage.
+        let weight = size_of::<K>() + size_of::<Entry<V>>() + size;
+        if matches!(self.max_bytes, Some(max_bytes) if weight > max_bytes) {
+            return;
+        }
+        let entry = Entry {
+            value: v,
+            expiry,
+            ttl,
+            hits: 0,
+            prefetching: false,
+            weight,
+        };
+        if let Some(old_entry) = self.lru_cache.put(k, entry) {
+            self.bytes -= old_entry.weight;
+        }
+        self.bytes += weight;
+
+        while self.lru_cache.len() > self.max_size
+            || matches!(self.max_bytes, Some(max_bytes) if self.bytes > max_bytes)
+        {
+            match self.lru_cache.pop_lru() {
+                Some((_, entry)) => {
+                    self.bytes -= entry.weight;
+                    self.stats.evictions += 1;
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&mut self, k: &K) {
+        if let Some(entry) = self.lru_cache.pop(k) {
+            self.bytes -= entry.weight;
+        }
+    }
+
+    /// Remove all entries, whose key matches `f`.
+    ///
+    /// Returns the number of removed entries.
+    pub(crate) fn remove_if<F>(&mut self, f: F) -> usize
+    where
+        F: Fn(&K) -> bool,
+    {
+        let keys: Vec<K> = self
+            .lru_cache
+            .iter()
+            .filter(|(k, _)| f(k))
+            .map(|(k, _)| k.clone())
+            .collect();
+        for k in &keys {
+            self.remove(k);
+        }
+        keys.len()
+    }
+
+    /// Remove all entries.
+    pub(crate) fn clear(&mut self) {
+        self.lru_cache.clear();
+        self.bytes = 0;
+    }
+
+    pub(crate) fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Get the number of entries.
+    pub(crate) fn len(&self) -> usize {
+        self.lru_cache.len()
+    }
+
+    /// Get the estimated memory usage of all entries in bytes.
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Iterate over all entries, also the expired ones, from the least recently used to the most
+    /// recently used entry.
+    ///
+    /// Every item contains the key, the value, the expiry and the TTL of the entry.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&K, &V, Instant, Duration)> {
+        self.lru_cache
+            .iter()
+            .rev()
+            .map(|(k, v)| (k, &v.value, v.expiry, v.ttl))
+    }
+
+    /// Iterate over the entries, which are not expired (see `entries`).
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&K, &V, Instant, Duration)> {
+        let now = Instant::now();
+        self.entries()
+            .filter(move |(_, _, expiry, _)| *expiry > now)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::mem::size_of;
     use std::thread::sleep;
     use std::time::Duration;
 
-    use super::Cache;
+    use super::{Cache, Entry};
 
     #[test]
     fn test_1() {
-        let mut cache: Cache<i32, i32> = Cache::new(2);
+        let mut cache: Cache<i32, i32> = Cache::new(2, None, 0);
         let d = Duration::from_secs(10);
 
-        cache.put(1, 4, d);
+        cache.put(1, 4, 0, d);
 
         assert_eq!(cache.get(&1), Some(&mut 4));
 
-        cache.put(2, 5, d);
-        cache.put(3, 6, d);
+        cache.put(2, 5, 0, d);
+        cache.put(3, 6, 0, d);
 
         assert_eq!(cache.get(&1), None);
     }
 
     #[test]
     fn test_2() {
-        let mut cache: Cache<i32, i32> = Cache::new(2);
+        let mut cache: Cache<i32, i32> = Cache::new(2, None, 0);
         let d = Duration::from_secs(10);
 
-        cache.put(1, 4, d);
-        cache.put(2, 5, d);
+        cache.put(1, 4, 0, d);
+        cache.put(2, 5, 0, d);
 
         assert_eq!(cache.get(&1), Some(&mut 4));
 
-        cache.put(3, 6, d);
+        cache.put(3, 6, 0, d);
 
         assert_eq!(cache.get(&1), Some(&mut 4));
     }
 
     #[test]
     fn test_3() {
-        let mut cache: Cache<i32, i32> = Cache::new(1);
+        let mut cache: Cache<i32, i32> = Cache::new(1, None, 0);
         let key = 10;
         let mut value = 20;
 
-        cache.put(key, value, Duration::from_secs(6));
+        cache.put(key, value, 0, Duration::from_secs(6));
 
         sleep(Duration::from_secs(3));
 
@@ -101,11 +325,11 @@ mod tests {
 
     #[test]
     fn test_4() {
-        let mut cache: Cache<i32, i32> = Cache::new(1);
+        let mut cache: Cache<i32, i32> = Cache::new(1, None, 0);
         let key = 10;
         let mut value = 20;
 
-        cache.put(key, value, Duration::from_secs(2));
+        cache.put(key, value, 0, Duration::from_secs(2));
 
         sleep(Duration::from_secs(1));
 
@@ -114,6 +338,154 @@ mod tests {
         sleep(Duration::from_secs(2));
 
         assert_eq!(cache.get_expired(&key), None);
-        assert_eq!(cache.get_expired_fallback(&key), Some(&mut value));
+        assert_eq!(
+            cache.get_stale(&key, Duration::from_secs(60)),
+            Some(&mut value)
+        );
+    }
+
+    #[test]
+    fn test_age() {
+        let mut cache: Cache<i32, i32> = Cache::new(1, None, 0);
+        let key = 10;
+
+        assert_eq!(cache.age(&key), None);
+
+        cache.put(key, 20, 0, Duration::from_secs(6));
+        assert!(cache.age(&key).unwrap() < Duration::from_secs(1));
+
+        sleep(Duration::from_secs(1));
+
+        let age = cache.age(&key).unwrap();
+        assert!(age >= Duration::from_secs(1));
+        assert!(age < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_stale() {
+        let mut cache: Cache<i32, i32> = Cache::new(1, None, 0);
+        let key = 10;
+        let max_age = Duration::from_secs(1);
+
+        cache.put(key, 20, 0, Duration::from_secs(1));
+
+        sleep(Duration::from_millis(1500));
+
+        assert_eq!(cache.get_expired(&key), None);
+        assert_eq!(cache.get_stale(&key, max_age), Some(&mut 20));
+
+        sleep(Duration::from_secs(1));
+
+        assert_eq!(cache.get_stale(&key, max_age), None);
+    }
+
+    #[test]
+    fn test_prefetch() {
+        let mut cache: Cache<i32, i32> = Cache::new(1, None, 50);
+        let key = 10;
+
+        cache.put(key, 20, 0, Duration::from_secs(2));
+
+        for _ in 0..3 {
+            assert_eq!(cache.get(&key), Some(&mut 20));
+        }
+        assert!(!cache.prefetch(&key));
+
+        sleep(Duration::from_millis(1100));
+
+        assert!(cache.prefetch(&key));
+        assert!(!cache.prefetch(&key));
+
+        // The refresh failed, so it can be started again.
+        cache.reset_prefetch(&key);
+        assert!(cache.prefetch(&key));
+
+        cache.put(key, 21, 0, Duration::from_secs(2));
+
+        assert!(!cache.prefetch(&key));
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut cache: Cache<i32, i32> = Cache::new(3, None, 0);
+        let d = Duration::from_secs(10);
+
+        cache.put(1, 4, 0, d);
+        cache.put(2, 5, 0, Duration::from_secs(0));
+        cache.put(3, 6, 0, d);
+        cache.get(&1);
+
+        let entries: Vec<(i32, i32)> = cache.iter().map(|(k, v, _, _)| (*k, *v)).collect();
+        assert_eq!(entries, vec![(3, 6), (1, 4)]);
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let weight = size_of::<i32>() + size_of::<Entry<i32>>();
+        let mut cache: Cache<i32, i32> = Cache::new(16, Some(2 * weight + 150), 0);
+        let d = Duration::from_secs(10);
+
+        cache.put(1, 4, 100, d);
+        cache.put(2, 5, 50, d);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.bytes(), 2 * weight + 150);
+
+        cache.put(3, 6, 50, d);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.bytes(), 2 * weight + 100);
+
+        cache.put(2, 7, 100, d);
+        assert_eq!(cache.get(&2), Some(&mut 7));
+        assert_eq!(cache.get(&3), Some(&mut 6));
+        assert_eq!(cache.bytes(), 2 * weight + 150);
+
+        // An entry, which is larger than the limit, does not evict the other entries.
+        cache.put(4, 8, 1000, d);
+        assert_eq!(cache.get(&4), None);
+        assert_eq!(cache.get(&2), Some(&mut 7));
+        assert_eq!(cache.get(&3), Some(&mut 6));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.bytes(), 2 * weight + 150);
+        assert_eq!(cache.stats().evictions, 1);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut cache: Cache<i32, i32> = Cache::new(2, None, 0);
+        let d = Duration::from_secs(10);
+
+        cache.put(1, 4, 0, d);
+        cache.put(2, 5, 0, d);
+        cache.put(3, 6, 0, d);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&mut 5));
+        assert_eq!(cache.get_expired(&3), Some(&mut 6));
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.evictions, 1);
+        assert!((stats.hit_ratio() - 2.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_remove_if() {
+        let mut cache: Cache<i32, i32> = Cache::new(4, None, 0);
+        let d = Duration::from_secs(10);
+
+        for k in 0..4 {
+            cache.put(k, k, 10, d);
+        }
+        let bytes = cache.bytes();
+        assert_eq!(cache.remove_if(|k| k % 2 == 0), 2);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.bytes(), bytes / 2);
+        assert_eq!(cache.get(&0), None);
+        assert_eq!(cache.get(&1), Some(&mut 1));
+
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.bytes(), 0);
     }
 }
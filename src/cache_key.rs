@@ -0,0 +1,58 @@
+use crate::edns::Edns;
+
+use dns_message_parser::{Flags, Question};
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// The key of a cached DNS response.
+///
+/// Every upstream has its own namespace in the cache, so the same question can be cached for
+/// different upstreams with different answers. The flags and the EDNS data of the request, which
+/// change the response, are part of the key, so that every client gets the response it asked for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    pub(crate) upstream: usize,
+    pub(crate) question: Question,
+    pub(crate) rd: bool,
+    pub(crate) cd: bool,
+    pub(crate) edns: Option<Edns>,
+}
+
+impl CacheKey {
+    pub(crate) fn new(
+        upstream: usize,
+        question: Question,
+        flags: &Flags,
+        edns: Option<Edns>,
+    ) -> CacheKey {
+        CacheKey {
+            upstream,
+            question,
+            rd: flags.rd,
+            cd: flags.cd,
+            edns,
+        }
+    }
+}
+
+impl Display for CacheKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{} (upstream {}", self.question, self.upstream)?;
+        if !self.rd {
+            write!(f, ", no rd")?;
+        }
+        if self.cd {
+            write!(f, ", cd")?;
+        }
+        if let Some(edns) = &self.edns {
+            write!(f, ", edns")?;
+            if edns.dnssec_ok {
+                write!(f, ", do")?;
+            }
+            if let Some(client_subnet) = &edns.client_subnet {
+                write!(f, ", ecs {:?}", client_subnet)?;
+            }
+        }
+        write!(f, ")")
+    }
+}
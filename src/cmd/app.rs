@@ -1,4 +1,6 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgGroup, SubCommand};
+
+use crate::control::DEFAULT_CONTROL_SOCKET;
 
 /// Get the `clap::App` object for the argument parsing.
 pub fn get_app() -> App<'static, 'static> {
@@ -37,12 +39,25 @@ pub fn get_app() -> App<'static, 'static> {
                 .takes_value(true)
                 .value_name("Addr/Name")
                 .help(
-                    "Remote address/hostname to the DOH server \
-                (If a hostname is used then another DNS server has to be configured)",
+                    "Remote address/hostname to the DOH server (If a hostname is used then another \
+                DNS server or --bootstrap has to be configured)",
                 )
                 .default_value("1.1.1.1:443")
                 .required(false),
         )
+        .arg(
+            Arg::with_name("bootstrap")
+                .long("bootstrap")
+                .takes_value(true)
+                .value_name("ADDRS")
+                .help(
+                    "Resolve the hostname of --remote-host with the plain DNS servers ADDRS \
+                (comma-separated, port 53 by default) instead of the system resolver. The \
+                hostname is resolved again after the TTL is expired or if the connection fails. \
+                Cannot be used with a socks5h proxy, which resolves the hostname itself",
+                )
+                .required(false),
+        )
         .arg(
             Arg::with_name("domain")
                 .short("d")
@@ -70,17 +85,136 @@ pub fn get_app() -> App<'static, 'static> {
                 .value_name("UNSIGNED LONG")
                 .help(
                     "The time in seconds after that the connection would be closed if no response \
-                is received from the server",
+                is received from the server. The request is retried on a new connection within \
+                this time and SERVFAIL is answered if it runs out",
+                )
+                .default_value("2")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("connect-timeout")
+                .takes_value(true)
+                .long("connect-timeout")
+                .value_name("UNSIGNED LONG")
+                .help(
+                    "The time in seconds after that a TCP (or socks5) connection attempt to the \
+                remote server is aborted",
                 )
                 .default_value("2")
                 .required(false),
         )
+        .arg(
+            Arg::with_name("handshake-timeout")
+                .takes_value(true)
+                .long("handshake-timeout")
+                .value_name("UNSIGNED LONG")
+                .help(
+                    "The time in seconds after that the TLS and HTTP2 handshake with the remote \
+                server is aborted",
+                )
+                .default_value("2")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("keepalive")
+                .takes_value(true)
+                .long("keepalive")
+                .value_name("UNSIGNED LONG")
+                .help(
+                    "The interval in seconds to send HTTP2 PINGs to the remote server. If no PONG \
+                is received, then the connection is closed and established again in the \
+                background (If the interval is 0 then no PINGs are sent)",
+                )
+                .default_value("30")
+                .required(false),
+        )
         .arg(
             Arg::with_name("cafile")
                 .takes_value(true)
                 .value_name("CAFILE")
-                .help("The path to the pem file, which contains the trusted CA certificates")
-                .required(true),
+                .help(
+                    "The path to the pem file, which contains the trusted CA certificates (If \
+                neither a CA file nor a CA directory is given, then the system trust store is \
+                used)",
+                )
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("cafiles")
+                .long("cafile")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("CAFILE")
+                .help("Trust also the CA certificates of this pem file")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("cadirs")
+                .long("cadir")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("DIR")
+                .help(
+                    "Trust also the CA certificates of the pem files (*.pem and *.crt) in this \
+                directory",
+                )
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("client-cert")
+                .long("client-cert")
+                .takes_value(true)
+                .value_name("FILE")
+                .requires("client-key")
+                .help(
+                    "The path to the pem file, which contains the client certificate (chain) for \
+                the DOH server. The client certificate and the private key are read again on \
+                SIGHUP and by the reload control command",
+                )
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("client-key")
+                .long("client-key")
+                .takes_value(true)
+                .value_name("FILE")
+                .requires("client-cert")
+                .help(
+                    "The path to the pem file, which contains the private key (PKCS#8, RSA or EC) \
+                of the client certificate",
+                )
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("pin")
+                .long("pin")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("SHA256")
+                .help(
+                    "Accept only a certificate of the DOH server with this SPKI pin (base64 \
+                encoded SHA-256 hash of the SubjectPublicKeyInfo). The pin of the server \
+                certificate is checked after the chain is validated with the CA certificates; \
+                the pins of intermediate and CA certificates are not accepted",
+                )
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("backup-pin")
+                .long("backup-pin")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("SHA256")
+                .requires("pin")
+                .help(
+                    "Accept also a certificate of the DOH server with this SPKI pin, but log a \
+                warning (e.g. the pin of the next key of the DOH server)",
+                )
+                .required(false),
         )
         .arg(
             Arg::with_name("path")
@@ -112,11 +246,268 @@ pub fn get_app() -> App<'static, 'static> {
                 .default_value("1024")
                 .required(false),
         )
+        .arg(
+            Arg::with_name("cache-max-bytes")
+                .long("cache-max-bytes")
+                .takes_value(true)
+                .value_name("UNSIGNED LONG")
+                .help(
+                    "The maximum estimated memory usage of the private HTTP cache in bytes, the \
+                least recently used entries are evicted first (If the value is 0 then only the \
+                cache size limits the cache)",
+                )
+                .default_value("0")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("prefetch")
+                .long("prefetch")
+                .takes_value(true)
+                .value_name("PERCENT")
+                .help(
+                    "Refresh popular cache entries in the background, if they are requested within \
+                the last PERCENT of their TTL (If the value is 0 then no entries are prefetched)",
+                )
+                .default_value("0")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("cache-file")
+                .long("cache-file")
+                .takes_value(true)
+                .value_name("PATH")
+                .help(
+                    "Save the cache to this file on shutdown and periodically, and load it on \
+                startup",
+                )
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("cache-save-interval")
+                .long("cache-save-interval")
+                .takes_value(true)
+                .value_name("UNSIGNED LONG")
+                .help(
+                    "The time in seconds between two saves of the cache file (If the value is 0 \
+                then the cache is only saved on shutdown)",
+                )
+                .default_value("300")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("min-ttl")
+                .long("min-ttl")
+                .takes_value(true)
+                .value_name("UNSIGNED LONG")
+                .help("The minimum time in seconds that responses are cached")
+                .default_value("0")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("max-ttl")
+                .long("max-ttl")
+                .takes_value(true)
+                .value_name("UNSIGNED LONG")
+                .help(
+                    "The maximum time in seconds that responses are cached (If the value is 0 then \
+                the time is not limited)",
+                )
+                .default_value("0")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("rewrite-ttl")
+                .long("rewrite-ttl")
+                .help("Rewrite the TTLs in the responses to the minimum and the maximum TTL")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("negative-ttl-max")
+                .long("negative-ttl-max")
+                .takes_value(true)
+                .value_name("UNSIGNED LONG")
+                .help(
+                    "The maximum time in seconds that NXDOMAIN and NODATA responses are cached \
+                (The time is taken from the SOA record, see RFC 2308)",
+                )
+                .default_value("3600")
+                .required(false),
+        )
         .arg(
             Arg::with_name("cache-fallback")
                 .long("cache-fallback")
-                .help("Use expired cache entries if no response is received from the server")
+                .help(
+                    "Use expired cache entries if no response is received from the server \
+                (serve-stale, see RFC 8767)",
+                )
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("stale-max-age")
+                .long("stale-max-age")
+                .takes_value(true)
+                .value_name("UNSIGNED LONG")
+                .help("The time in seconds after the expiry, during that a cache entry may be used")
+                .default_value("86400")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("stale-timeout")
+                .long("stale-timeout")
+                .takes_value(true)
+                .value_name("UNSIGNED LONG")
+                .help(
+                    "The time in milliseconds after that an expired cache entry is answered, if no \
+                response is received from the server yet. The cache entry is refreshed in the \
+                background",
+                )
+                .default_value("1800")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("forward")
+                .long("forward")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("SUFFIX=ADDRS")
+                .help(
+                    "Forward all queries for the domain SUFFIX and its subdomains to the plain DNS \
+                servers ADDRS (comma-separated, port 53 by default) instead of the DOH server\n\
+                (example: corp.example.com=10.0.0.53,10.0.1.53 or consul=127.0.0.1:8600)",
+                )
                 .required(false),
+        )
+        .arg(
+            Arg::with_name("control-socket")
+                .long("control-socket")
+                .takes_value(true)
+                .value_name("PATH")
+                .help(
+                    "Open a unix control socket at PATH, which is used by the ctl subcommand to \
+                administrate the running client (e.g. to flush the cache)",
+                )
+                .required(false),
+        )
+        .subcommand(
+            SubCommand::with_name("ctl")
+                .about("Send a command to the control socket of a running client")
+                .arg(
+                    Arg::with_name("socket")
+                        .short("s")
+                        .long("socket")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .help("The path to the control socket")
+                        .default_value(DEFAULT_CONTROL_SOCKET)
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .multiple(true)
+                        .value_name("COMMAND")
+                        .help(
+                            "The command and its arguments:\n\
+                        status                    Show the upstreams and their connection state\n\
+                        reconnect                 Connect to the DOH server again\n\
+                        switch ADDRS              Use the addresses ADDRS (comma-separated)\n\
+                        resolve                   Resolve the DOH server again (--bootstrap)\n\
+                        reload                    Read the client certificate again\n\
+                        log [LEVEL|reset]         Set the log level or use RUST_LOG again\n\
+                        cache flush [NAME]        Remove all entries or the entries of NAME\n\
+                        cache flush-suffix SUFFIX Remove the entries of SUFFIX and its subdomains\n\
+                        cache lookup NAME [TYPE]  Show the entries of NAME and the remaining TTL\n\
+                        cache stats               Show the size, the hit ratio and the evictions",
+                        )
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("query")
+                .about(
+                    "Send a single query to the DOH server and print the response, the HTTP \
+                status, some headers and the timing. The options of the DOH server are used",
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the result as JSON")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .value_name("NAME")
+                        .help("The domain name to query")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("type")
+                        .value_name("TYPE")
+                        .help("The type to query (e.g. A, AAAA, MX, TXT or ANY)")
+                        .default_value("A")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about(
+                    "Send many queries to the DOH server and print the queries per second, the \
+                latency percentiles, the errors and the reconnects. The options of the DOH server \
+                are used",
+                )
+                .arg(
+                    Arg::with_name("names")
+                        .long("names")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Query the domain names of FILE (one per line)")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("random")
+                        .long("random")
+                        .takes_value(true)
+                        .value_name("SUFFIX")
+                        .help(
+                            "Query random subdomains of SUFFIX, so that the responses are not \
+                        cached by the server",
+                        )
+                        .required(false),
+                )
+                .group(
+                    ArgGroup::with_name("domain-names")
+                        .args(&["names", "random"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("type")
+                        .long("type")
+                        .takes_value(true)
+                        .value_name("TYPE")
+                        .help("The type to query (e.g. A, AAAA, MX, TXT or ANY)")
+                        .default_value("A")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("requests")
+                        .short("n")
+                        .long("requests")
+                        .takes_value(true)
+                        .value_name("UNSIGNED INT")
+                        .help("The number of queries")
+                        .default_value("1000")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("concurrency")
+                        .short("c")
+                        .long("concurrency")
+                        .takes_value(true)
+                        .value_name("UNSIGNED INT")
+                        .help("The number of queries, which are in flight at the same time")
+                        .default_value("10")
+                        .required(false),
+                ),
         );
 
     #[cfg(feature = "socks5")]
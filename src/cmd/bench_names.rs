@@ -0,0 +1,53 @@
+use crate::BenchNames;
+
+use clap::ArgMatches;
+
+use dns_message_parser::{DomainError, DomainName};
+
+use std::convert::TryFrom;
+use std::fs::read_to_string;
+use std::io::Error as IoError;
+
+#[derive(Debug)]
+pub enum BenchNamesError {
+    Io(String, IoError),
+    Domain(String, DomainError),
+    Empty(String),
+}
+
+/// Read the domain names of the file `path`, one per line. Empty lines and lines, which start
+/// with `#`, are skipped.
+fn read_names(path: &str) -> Result<Vec<DomainName>, BenchNamesError> {
+    let content = match read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => return Err(BenchNamesError::Io(path.to_string(), e)),
+    };
+    let mut domain_names = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match DomainName::try_from(line) {
+            Ok(domain_name) => domain_names.push(domain_name),
+            Err(e) => return Err(BenchNamesError::Domain(line.to_string(), e)),
+        }
+    }
+    if domain_names.is_empty() {
+        return Err(BenchNamesError::Empty(path.to_string()));
+    }
+    Ok(domain_names)
+}
+
+/// Get the domain names of the `bench` subcommand.
+pub fn get_bench_names(arg_matches: &ArgMatches) -> Result<BenchNames, BenchNamesError> {
+    if let Some(path) = arg_matches.value_of("names") {
+        Ok(BenchNames::list(read_names(path)?))
+    } else {
+        let suffix = arg_matches.value_of("random").unwrap();
+        match BenchNames::random(suffix) {
+            Ok(bench_names) => Ok(bench_names),
+            Err(e) => Err(BenchNamesError::Domain(suffix.to_string(), e)),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use crate::ForwardRule;
+
+use clap::ArgMatches;
+
+use dns_message_parser::DomainError;
+
+use std::net::{AddrParseError, IpAddr, SocketAddr};
+
+#[derive(Debug)]
+pub enum ForwardRuleError {
+    Syntax(String),
+    Domain(String, DomainError),
+    Addr(String, AddrParseError),
+}
+
+pub(super) fn get_remote_addr(remote_addr: &str) -> Result<SocketAddr, AddrParseError> {
+    match remote_addr.parse() {
+        Ok(remote_addr) => Ok(remote_addr),
+        Err(e) => match remote_addr.parse::<IpAddr>() {
+            Ok(ip_addr) => Ok(SocketAddr::new(ip_addr, 53)),
+            Err(_) => Err(e),
+        },
+    }
+}
+
+fn get_forward_rule(forward_rule: &str) -> Result<ForwardRule, ForwardRuleError> {
+    let suffix_remote_addrs: Vec<&str> = forward_rule.splitn(2, '=').collect();
+    if suffix_remote_addrs.len() != 2 {
+        return Err(ForwardRuleError::Syntax(forward_rule.to_string()));
+    }
+
+    let mut remote_addrs = Vec::new();
+    for remote_addr in suffix_remote_addrs[1].split(',') {
+        match get_remote_addr(remote_addr.trim()) {
+            Ok(remote_addr) => remote_addrs.push(remote_addr),
+            Err(e) => return Err(ForwardRuleError::Addr(remote_addr.to_string(), e)),
+        }
+    }
+
+    let suffix = suffix_remote_addrs[0];
+    match ForwardRule::new(suffix, remote_addrs) {
+        Ok(forward_rule) => Ok(forward_rule),
+        Err(e) => Err(ForwardRuleError::Domain(suffix.to_string(), e)),
+    }
+}
+
+pub fn get_forward_rules(arg_matches: &ArgMatches) -> Result<Vec<ForwardRule>, ForwardRuleError> {
+    let mut forward_rules = Vec::new();
+    if let Some(values) = arg_matches.values_of("forward") {
+        for value in values {
+            forward_rules.push(get_forward_rule(value)?);
+        }
+    }
+    Ok(forward_rules)
+}
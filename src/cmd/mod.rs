@@ -1,8 +1,20 @@
 mod app;
 pub use app::get_app;
 
+mod bench_names;
+pub use bench_names::get_bench_names;
+
+mod forward_rules;
+pub use forward_rules::get_forward_rules;
+
+mod pins;
+pub use pins::{get_backup_pins, get_pins};
+
+mod question;
+pub use question::{get_qtype, get_question};
+
 mod listen_config;
 pub use listen_config::get_listen_config;
 
 mod remote_host;
-pub use remote_host::get_remote_host;
+pub use remote_host::{get_bootstrap, get_remote_host};
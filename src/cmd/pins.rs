@@ -0,0 +1,42 @@
+use crate::Pin;
+
+use base64::{decode, DecodeError};
+
+use clap::ArgMatches;
+
+#[derive(Debug)]
+pub enum PinError {
+    Base64(String, DecodeError),
+    Length(String),
+}
+
+fn get_pin(pin: &str) -> Result<Pin, PinError> {
+    let hash = match decode(pin) {
+        Ok(hash) => hash,
+        Err(e) => return Err(PinError::Base64(pin.to_string(), e)),
+    };
+    match Pin::new(&hash) {
+        Some(pin) => Ok(pin),
+        None => Err(PinError::Length(pin.to_string())),
+    }
+}
+
+fn get_pins_of(arg_matches: &ArgMatches, name: &str) -> Result<Vec<Pin>, PinError> {
+    let mut pins = Vec::new();
+    if let Some(values) = arg_matches.values_of(name) {
+        for value in values {
+            pins.push(get_pin(value)?);
+        }
+    }
+    Ok(pins)
+}
+
+/// Get the SPKI pins of the DoH server.
+pub fn get_pins(arg_matches: &ArgMatches) -> Result<Vec<Pin>, PinError> {
+    get_pins_of(arg_matches, "pin")
+}
+
+/// Get the backup SPKI pins of the DoH server.
+pub fn get_backup_pins(arg_matches: &ArgMatches) -> Result<Vec<Pin>, PinError> {
+    get_pins_of(arg_matches, "backup-pin")
+}
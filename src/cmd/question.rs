@@ -0,0 +1,59 @@
+use clap::ArgMatches;
+
+use dns_message_parser::{Class, DomainError, DomainName, QClass, QType, QType_, Question, Type};
+
+use std::convert::TryFrom;
+
+#[derive(Debug)]
+pub enum QuestionError {
+    Domain(String, DomainError),
+    Type(String),
+}
+
+/// Parse the name of a query type, which is case-insensitive.
+fn parse_qtype(qtype: &str) -> Result<QType, QuestionError> {
+    let type_ = match qtype.to_uppercase().as_str() {
+        "A" => Type::A,
+        "NS" => Type::NS,
+        "CNAME" => Type::CNAME,
+        "SOA" => Type::SOA,
+        "PTR" => Type::PTR,
+        "HINFO" => Type::HINFO,
+        "MX" => Type::MX,
+        "TXT" => Type::TXT,
+        "AAAA" => Type::AAAA,
+        "LOC" => Type::LOC,
+        "SRV" => Type::SRV,
+        "NAPTR" => Type::NAPTR,
+        "DNAME" => Type::DNAME,
+        "DS" => Type::DS,
+        "SSHFP" => Type::SSHFP,
+        "RRSIG" => Type::RRSIG,
+        "NSEC" => Type::NSEC,
+        "DNSKEY" => Type::DNSKEY,
+        "NSEC3" => Type::NSEC3,
+        "TLSA" => Type::TLSA,
+        "SPF" => Type::SPF,
+        "URI" => Type::URI,
+        "CAA" => Type::CAA,
+        "ANY" => return Ok(QType::QType(QType_::ALL)),
+        _ => return Err(QuestionError::Type(qtype.to_string())),
+    };
+    Ok(QType::Type(type_))
+}
+
+/// Get the query type of the `query` or the `bench` subcommand.
+pub fn get_qtype(arg_matches: &ArgMatches) -> Result<QType, QuestionError> {
+    parse_qtype(arg_matches.value_of("type").unwrap())
+}
+
+/// Get the question of the `query` subcommand.
+pub fn get_question(arg_matches: &ArgMatches) -> Result<Question, QuestionError> {
+    let name = arg_matches.value_of("name").unwrap();
+    let domain_name = match DomainName::try_from(name) {
+        Ok(domain_name) => domain_name,
+        Err(e) => return Err(QuestionError::Domain(name.to_string(), e)),
+    };
+    let qtype = get_qtype(arg_matches)?;
+    Ok(Question::new(domain_name, QClass::Class(Class::IN), qtype))
+}
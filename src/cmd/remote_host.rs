@@ -1,4 +1,6 @@
-use crate::RemoteHost;
+use crate::{Bootstrap, RemoteHost};
+
+use super::forward_rules::get_remote_addr;
 
 use clap::ArgMatches;
 
@@ -6,9 +8,10 @@ use clap::ArgMatches;
 use std::borrow::Cow;
 use std::io::Error as IoError;
 use std::iter::FromIterator;
+use std::net::IpAddr;
 use std::net::SocketAddr;
 #[cfg(feature = "socks5")]
-use std::net::{IpAddr, SocketAddrV4, SocketAddrV6};
+use std::net::{SocketAddrV4, SocketAddrV6};
 
 #[cfg(feature = "socks5")]
 use tokio_socks::TargetAddr;
@@ -27,6 +30,9 @@ pub enum RemoteHostError {
     CouldNotResolve(String),
     UnknownPort(String),
     UnknownHost(String),
+    Bootstrap(String),
+    #[cfg(feature = "socks5")]
+    BootstrapWithSocks5h,
 }
 
 #[cfg(feature = "socks5")]
@@ -42,10 +48,21 @@ impl From<IoError> for RemoteHostError {
     }
 }
 
+fn get_host_port(remote_host: &str) -> Result<(&str, u16), RemoteHostError> {
+    let host_port: Vec<&str> = remote_host.rsplitn(2, ':').collect();
+    if host_port.len() != 2 {
+        return Err(RemoteHostError::UnknownHost(remote_host.to_string()));
+    }
+    match host_port[0].parse() {
+        Ok(port) => Ok((host_port[1], port)),
+        Err(_) => Err(RemoteHostError::UnknownPort(remote_host.to_string())),
+    }
+}
+
 #[cfg(feature = "socks5")]
 fn get_socks5_url(arg_matches: &ArgMatches) -> Result<Option<Url>, ParseError> {
     if let Some(socks5) = arg_matches.value_of("socks5") {
-        let url = Url::parse(&socks5)?;
+        let url = Url::parse(socks5)?;
         return Ok(Some(url));
     }
 
@@ -102,17 +119,7 @@ async fn get_dest_addrs(
     url: &Url,
 ) -> Result<Vec<TargetAddr<'static>>, RemoteHostError> {
     let remote_host = String::from(arg_matches.value_of("remote-host").unwrap());
-
-    let dest_addr: Vec<&str> = remote_host.rsplitn(2, ':').collect();
-    if dest_addr.len() != 2 {
-        return Err(RemoteHostError::UnknownHost(remote_host));
-    }
-    let host = dest_addr[1];
-    let port = if let Ok(port) = dest_addr[0].parse() {
-        port
-    } else {
-        return Err(RemoteHostError::UnknownPort(remote_host));
-    };
+    let (host, port) = get_host_port(&remote_host)?;
 
     match host.parse::<IpAddr>() {
         Ok(dest_addr) => {
@@ -122,7 +129,10 @@ async fn get_dest_addrs(
         }
         Err(_) => {
             let resolve = get_resolve(url)?;
-            if resolve {
+            if resolve && arg_matches.is_present("bootstrap") {
+                // The addresses are resolved by the bootstrap resolver on the first connect.
+                Ok(Vec::new())
+            } else if resolve {
                 let dest_addrs = lookup_host(&remote_host).await?;
                 let dest_addrs = dest_addrs.map(TargetAddr::Ip).collect();
                 Ok(dest_addrs)
@@ -141,10 +151,8 @@ fn get_credentials(url: &Url) -> Option<(String, String)> {
 
     if username.is_empty() {
         None
-    } else if let Some(password) = password {
-        Some((username.to_string(), password.to_string()))
     } else {
-        None
+        password.map(|password| (username.to_string(), password.to_string()))
     }
 }
 #[cfg(feature = "socks5")]
@@ -176,6 +184,8 @@ async fn get_remote_addrs(
 
     match remote_host.parse() {
         Ok(addr) => Ok(vec![addr]),
+        // The addresses are resolved by the bootstrap resolver on the first connect.
+        Err(_) if arg_matches.is_present("bootstrap") => Ok(Vec::new()),
         Err(_) => {
             let addr = lookup_host(remote_host).await?;
             Ok(Vec::from_iter(addr))
@@ -183,6 +193,45 @@ async fn get_remote_addrs(
     }
 }
 
+pub fn get_bootstrap(arg_matches: &ArgMatches) -> Result<Option<Bootstrap>, RemoteHostError> {
+    let bootstrap = match arg_matches.value_of("bootstrap") {
+        Some(bootstrap) => bootstrap,
+        None => return Ok(None),
+    };
+
+    let remote_host = arg_matches.value_of("remote-host").unwrap();
+    let (host, port) = get_host_port(remote_host)?;
+    if host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .is_ok()
+    {
+        return Ok(None);
+    }
+
+    // With socks5h the hostname is resolved by the proxy, so resolving it locally would leak it.
+    #[cfg(feature = "socks5")]
+    if let Some(url) = get_socks5_url(arg_matches)? {
+        if !get_resolve(&url)? {
+            return Err(RemoteHostError::BootstrapWithSocks5h);
+        }
+    }
+
+    let mut remote_addrs = Vec::new();
+    for remote_addr in bootstrap.split(',') {
+        match get_remote_addr(remote_addr.trim()) {
+            Ok(remote_addr) => remote_addrs.push(remote_addr),
+            Err(_) => return Err(RemoteHostError::Bootstrap(remote_addr.to_string())),
+        }
+    }
+
+    match Bootstrap::new(host, port, remote_addrs) {
+        Ok(bootstrap) => Ok(Some(bootstrap)),
+        Err(_) => Err(RemoteHostError::UnknownHost(host.to_string())),
+    }
+}
+
 pub async fn get_remote_host(
     arg_matches: &ArgMatches<'static>,
 ) -> Result<RemoteHost, RemoteHostError> {
@@ -1,100 +1,243 @@
+use crate::ca::create_root_store;
+use crate::client_cert::ClientCert;
+use crate::forward::{Router, Rule as ForwardRule};
 use crate::listen::{handler as listen_handler, Config as ListenConfig};
-use crate::remote::{Host as RemoteHost, Session as RemoteSession};
-use crate::{Cache, Context, DohError, DohResult};
-
-use futures::lock::Mutex;
+use crate::remote::{
+    Bootstrap, Host as RemoteHost, Pin, PinVerifier, PlainSession, Session as RemoteSession,
+    SessionConfig, Upstream,
+};
+use crate::{Context, DohError, DohResult};
 
 use tokio::net::udp::RecvHalf;
+use tokio::net::UnixListener;
 
 use rustls::ClientConfig;
 
-use std::fs::File;
-use std::io::BufReader;
+use std::fs::{remove_file, set_permissions, symlink_metadata, Permissions};
 use std::io::Result as IoResult;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
 use std::sync::Arc;
+use std::time::Duration;
 
-fn create_client_config(cafile: &str) -> DohResult<ClientConfig> {
-    let certfile = File::open(&cafile)?;
+fn create_client_config(tls: TlsOptions) -> DohResult<(ClientConfig, Option<Arc<ClientCert>>)> {
+    let client_cert = match &tls.client_cert {
+        Some((cert_file, key_file)) => Some(Arc::new(ClientCert::new(cert_file, key_file)?)),
+        None => None,
+    };
+    let cafiles: Vec<&str> = tls.cafiles.iter().map(String::as_str).collect();
+    let cadirs: Vec<&str> = tls.cadirs.iter().map(String::as_str).collect();
     let mut config = ClientConfig::new();
-    if let Err(()) = config
-        .root_store
-        .add_pem_file(&mut BufReader::new(certfile))
-    {
-        return Err(DohError::PEMParser);
+    config.root_store = create_root_store(&cafiles, &cadirs)?;
+    if let Some(client_cert) = &client_cert {
+        config.client_auth_cert_resolver = client_cert.clone();
+    }
+    if !tls.pins.is_empty() {
+        let verifier = PinVerifier::new(tls.pins, tls.backup_pins);
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(verifier));
+    } else if !tls.backup_pins.is_empty() {
+        return Err(DohError::BackupPinWithoutPin);
     }
     config.alpn_protocols.push(vec![104, 50]); // h2
-    Ok(config)
+    Ok((config, client_cert))
+}
+
+fn get_keepalive(keepalive: u64) -> Option<Duration> {
+    if keepalive == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(keepalive))
+    }
+}
+
+fn create_remote_session(
+    remote: RemoteOptions,
+    client_config: Arc<ClientConfig>,
+    uri: String,
+) -> RemoteSession {
+    let config = SessionConfig {
+        domain: remote.domain,
+        client_config,
+        uri,
+        retries: remote.retries,
+        timeout: Duration::from_secs(remote.timeout),
+        connect_timeout: Duration::from_secs(remote.connect_timeout),
+        handshake_timeout: Duration::from_secs(remote.handshake_timeout),
+        keepalive: get_keepalive(remote.keepalive),
+        post: remote.post,
+    };
+    RemoteSession::new(remote.host, config, remote.bootstrap)
+}
+
+/// Bind the control socket at `path`, which is only accessible by its owner. A socket of a
+/// previous run is removed before, but no other files.
+fn bind_control_socket(path: &str) -> IoResult<UnixListener> {
+    if let Ok(metadata) = symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            remove_file(path)?;
+        }
+    }
+    info!("Control socket: {}", path);
+    let listener = UnixListener::bind(path)?;
+    set_permissions(path, Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// The options of the connection to the DoH server.
+pub struct RemoteOptions {
+    pub host: RemoteHost,
+    /// The hostname of the server, which is used for SNI and the certificate validation.
+    pub domain: String,
+    /// The path of the URI without the leading slash.
+    pub path: String,
+    pub retries: u32,
+    /// The timeout of a request in seconds.
+    pub timeout: u64,
+    /// The timeout of a TCP (or socks5) connection attempt in seconds.
+    pub connect_timeout: u64,
+    /// The timeout of the TLS and HTTP2 handshake in seconds.
+    pub handshake_timeout: u64,
+    /// The interval of the HTTP2 PINGs in seconds (0 disables them).
+    pub keepalive: u64,
+    /// Send the requests with POST instead of GET.
+    pub post: bool,
+    pub bootstrap: Option<Bootstrap>,
+}
+
+/// The options of the TLS connection to the DoH server.
+///
+/// If neither CA files nor directories are given, then the system trust store is used.
+pub struct TlsOptions {
+    pub cafiles: Vec<String>,
+    pub cadirs: Vec<String>,
+    pub pins: Vec<Pin>,
+    pub backup_pins: Vec<Pin>,
+    /// The pem files of the client certificate and its private key.
+    pub client_cert: Option<(String, String)>,
+}
+
+/// The options of serving expired cache entries (see RFC 8767).
+pub struct StaleOptions {
+    /// The time in seconds after the expiry, during that a cache entry may be used.
+    pub max_age: u64,
+    /// The time in milliseconds after that an expired cache entry is answered.
+    pub timeout: u64,
+}
+
+/// The options of the cache.
+pub struct CacheOptions {
+    /// The maximum number of entries (0 disables the cache).
+    pub size: usize,
+    /// The maximum estimated memory usage in bytes (0 means no limit).
+    pub max_bytes: usize,
+    /// The percentage of the TTL, in which often requested entries are refreshed (0 disables it).
+    pub prefetch: u32,
+    /// The file, which the cache is saved to and loaded from.
+    pub file: Option<String>,
+    /// The time in seconds between two saves of the cache file (0 saves only on shutdown).
+    pub save_interval: u64,
+    /// Answer with expired entries, if no response is received from the server.
+    pub fallback: Option<StaleOptions>,
+}
+
+/// The limits of the time that responses are cached.
+pub struct TtlOptions {
+    /// The minimum time in seconds.
+    pub min: u64,
+    /// The maximum time in seconds (0 means no limit).
+    pub max: u64,
+    /// Rewrite the TTLs in the responses to the limits.
+    pub rewrite: bool,
+    /// The maximum time in seconds for NXDOMAIN and NODATA responses.
+    pub negative_max: u64,
 }
 
 /// The configuration object for the `doh-client`.
 pub struct Config {
     listen_config: ListenConfig,
-    remote_host: RemoteHost,
-    domain: String,
+    control_socket: Option<String>,
+    remote: RemoteOptions,
     client_config: Arc<ClientConfig>,
+    client_cert: Option<Arc<ClientCert>>,
     uri: String,
-    retries: u32,
-    timeout: u64,
-    post: bool,
-    cache_size: usize,
-    cache_fallback: bool,
+    cache: CacheOptions,
+    ttl: TtlOptions,
+    forward_rules: Vec<ForwardRule>,
 }
 
 impl Config {
     /// Create a new `doh_client::Config` object.
     pub fn new(
         listen_config: ListenConfig,
-        remote_host: RemoteHost,
-        domain: &str,
-        cafile: &str,
-        path: &str,
-        retries: u32,
-        timeout: u64,
-        post: bool,
-        cache_size: usize,
-        cache_fallback: bool,
+        control_socket: Option<String>,
+        remote: RemoteOptions,
+        tls: TlsOptions,
+        cache: CacheOptions,
+        ttl: TtlOptions,
+        forward_rules: Vec<ForwardRule>,
     ) -> DohResult<Config> {
-        let client_config = create_client_config(&cafile)?;
+        let (client_config, client_cert) = create_client_config(tls)?;
 
-        let uri = format!("https://{}/{}", domain, path);
+        let uri = format!("https://{}/{}", remote.domain, remote.path);
 
-        if cache_fallback && cache_size == 0 {
+        if (cache.fallback.is_some() || cache.file.is_some()) && cache.size == 0 {
             return Err(DohError::CacheSize);
         }
 
+        if ttl.max != 0 && ttl.min > ttl.max {
+            return Err(DohError::CacheTtl);
+        }
+
         Ok(Config {
             listen_config,
-            remote_host,
-            domain: domain.to_string(),
+            control_socket,
+            remote,
             client_config: Arc::new(client_config),
+            client_cert,
             uri,
-            retries,
-            timeout,
-            post,
-            cache_size,
-            cache_fallback,
+            cache,
+            ttl,
+            forward_rules,
         })
     }
 
-    pub(crate) async fn into(self) -> IoResult<(RecvHalf, Context)> {
-        let cache = if self.cache_size == 0 {
-            None
-        } else {
-            Some(Mutex::new(Cache::new(self.cache_size)))
-        };
-        let cache_fallback = self.cache_fallback;
-        let timeout = self.timeout;
+    /// Create the session to the DoH server, which is used by `doh-client query`.
+    pub(crate) fn into_remote_session(self) -> RemoteSession {
+        create_remote_session(self.remote, self.client_config, self.uri)
+    }
+
+    pub(crate) async fn into(self) -> IoResult<(RecvHalf, Option<UnixListener>, Context)> {
+        let router = Router::new(&self.forward_rules);
+        let timeout = self.remote.timeout;
+        let keepalive = get_keepalive(self.remote.keepalive);
         let socket = self.listen_config.into_socket().await?;
+        let control_listener = match &self.control_socket {
+            Some(control_socket) => Some(bind_control_socket(control_socket)?),
+            None => None,
+        };
         let (recv, sender) = listen_handler(socket);
-        let remote_session = RemoteSession::new(
-            self.remote_host,
-            self.domain,
-            self.client_config,
-            self.uri,
-            self.retries,
-            self.post,
+        let remote_session = create_remote_session(self.remote, self.client_config, self.uri);
+        let mut upstreams = vec![Upstream::Doh(Box::new(remote_session))];
+        for forward_rule in self.forward_rules {
+            info!("Forward rule: {}", forward_rule);
+            // The handler sends a request again, if it fails, and every request starts with the
+            // next server. Therefore, the session makes only one attempt, which may use the whole
+            // time.
+            let plain_session =
+                PlainSession::new(forward_rule.remote_addrs, 1, Duration::from_secs(timeout));
+            upstreams.push(Upstream::Plain(plain_session));
+        }
+        let context = Context::new(
+            self.cache,
+            self.ttl,
+            timeout,
+            keepalive,
+            upstreams,
+            router,
+            sender,
+            self.client_cert,
         );
-        let context = Context::new(cache, cache_fallback, timeout, remote_session, sender);
-        Ok((recv, context))
+        Ok((recv, control_listener, context))
     }
 }
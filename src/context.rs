@@ -1,40 +1,124 @@
 use bytes::Bytes;
 
-use crate::remote::Session as RemoteSession;
-use crate::Cache;
+use crate::client_cert::ClientCert;
+use crate::forward::Router;
+use crate::in_flight::InFlight;
+use crate::persist::{load, Persist};
+use crate::remote::Upstream;
+use crate::stale::ServeStale;
+use crate::ttl::TtlLimits;
+use crate::{CacheKey, CacheOptions, ShardedCache, TtlOptions};
 
-use dns_message_parser::{Dns, Question};
+use dns_message_parser::Dns;
 
 use futures::channel::mpsc::UnboundedSender;
 use futures::lock::Mutex;
 
+use std::io::ErrorKind;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
+/// Create the cache and load the entries of the cache file, if one is configured.
+fn create_cache(cache: &CacheOptions, router: &Router) -> Option<ShardedCache<CacheKey, Dns>> {
+    if cache.size == 0 {
+        return None;
+    }
+    let max_bytes = if cache.max_bytes == 0 {
+        None
+    } else {
+        Some(cache.max_bytes)
+    };
+    let sharded_cache = ShardedCache::new(cache.size, max_bytes, cache.prefetch);
+    if let Some(cache_file) = &cache.file {
+        match load(&sharded_cache, router, cache_file) {
+            Ok(n) => info!(
+                "Loaded {} cache entries ({} bytes) from {}",
+                n,
+                sharded_cache.bytes(),
+                cache_file
+            ),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => warn!("Could not load the cache from {}: {}", cache_file, e),
+        }
+    }
+    Some(sharded_cache)
+}
+
+fn create_serve_stale(cache: &CacheOptions) -> Option<ServeStale> {
+    cache.fallback.as_ref().map(|stale| {
+        let max_age = Duration::from_secs(stale.max_age);
+        let timeout = Duration::from_millis(stale.timeout);
+        ServeStale::new(max_age, timeout)
+    })
+}
+
+fn create_persist(cache: CacheOptions) -> Option<Persist> {
+    let save_interval = if cache.save_interval == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(cache.save_interval))
+    };
+    cache
+        .file
+        .map(|cache_file| Persist::new(cache_file, save_interval))
+}
+
+fn create_ttl_limits(ttl: TtlOptions) -> TtlLimits {
+    let max_ttl = if ttl.max == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(ttl.max))
+    };
+    TtlLimits::new(
+        Duration::from_secs(ttl.min),
+        max_ttl,
+        Duration::from_secs(ttl.negative_max),
+        ttl.rewrite,
+    )
+}
+
 /// The context object for a running instance.
 pub struct Context {
     pub(crate) sender: UnboundedSender<(Bytes, SocketAddr)>,
-    pub(crate) remote_session: Mutex<RemoteSession>,
-    pub(crate) cache: Option<Mutex<Cache<Question, Dns>>>,
-    pub(super) cache_fallback: bool,
+    pub(crate) upstreams: Vec<Mutex<Upstream>>,
+    pub(crate) router: Router,
+    pub(crate) cache: Option<ShardedCache<CacheKey, Dns>>,
+    pub(crate) in_flight: InFlight,
+    pub(crate) persist: Option<Persist>,
+    pub(crate) ttl_limits: TtlLimits,
+    pub(crate) serve_stale: Option<ServeStale>,
     pub(crate) timeout: Duration,
+    pub(crate) keepalive: Option<Duration>,
+    pub(crate) client_cert: Option<Arc<ClientCert>>,
 }
 
 impl Context {
     /// Create a new `doh_client::Context` object.
     pub(super) fn new(
-        cache: Option<Mutex<Cache<Question, Dns>>>,
-        cache_fallback: bool,
+        cache_options: CacheOptions,
+        ttl_options: TtlOptions,
         timeout: u64,
-        remote_session: RemoteSession,
+        keepalive: Option<Duration>,
+        upstreams: Vec<Upstream>,
+        router: Router,
         sender: UnboundedSender<(Bytes, SocketAddr)>,
+        client_cert: Option<Arc<ClientCert>>,
     ) -> Context {
+        let cache = create_cache(&cache_options, &router);
+        let serve_stale = create_serve_stale(&cache_options);
         Context {
             sender,
-            remote_session: Mutex::new(remote_session),
+            upstreams: upstreams.into_iter().map(Mutex::new).collect(),
+            router,
             cache,
-            cache_fallback,
+            in_flight: InFlight::new(),
+            persist: create_persist(cache_options),
+            ttl_limits: create_ttl_limits(ttl_options),
+            serve_stale,
             timeout: Duration::from_secs(timeout),
+            keepalive,
+            client_cert,
         }
     }
 }
@@ -0,0 +1,167 @@
+use crate::forward::is_subdomain;
+use crate::{CacheKey, Context, ShardedCache};
+
+use dns_message_parser::{Dns, DomainName};
+
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::time::Instant;
+
+use super::CommandResult;
+
+const USAGE: &str = "Usage: cache flush [NAME] | cache flush-suffix SUFFIX | \
+                     cache lookup NAME [TYPE] | cache stats";
+
+/// Parse the domain name `name` and convert it to lowercase, because domain names are compared
+/// case-insensitively.
+fn parse_domain_name(name: &str) -> Result<String, String> {
+    let domain_name = if name == "." {
+        DomainName::default()
+    } else {
+        DomainName::try_from(name).map_err(|e| format!("Invalid domain name {}: {:?}", name, e))?
+    };
+    Ok(domain_name.to_string().to_lowercase())
+}
+
+fn domain_name(cache_key: &CacheKey) -> String {
+    cache_key
+        .question
+        .get_domain_name()
+        .to_string()
+        .to_lowercase()
+}
+
+fn flush<F>(cache: &ShardedCache<CacheKey, Dns>, f: F) -> CommandResult
+where
+    F: Fn(&str) -> bool,
+{
+    let removed = cache.remove_if(|cache_key| f(&domain_name(cache_key)));
+    Ok(format!("Removed {} entries\n", removed))
+}
+
+fn lookup(cache: &ShardedCache<CacheKey, Dns>, name: &str, qtype: Option<&str>) -> CommandResult {
+    let name = parse_domain_name(name)?;
+    let now = Instant::now();
+    let mut output = String::new();
+    cache.for_each(|cache| {
+        for (cache_key, dns, expiry, _) in cache.entries() {
+            if domain_name(cache_key) != name {
+                continue;
+            }
+            if let Some(qtype) = qtype {
+                let other = cache_key.question.get_qtype().to_string();
+                if !other.eq_ignore_ascii_case(qtype) {
+                    continue;
+                }
+            }
+            let remaining = if expiry > now {
+                format!("expires in {}s", (expiry - now).as_secs())
+            } else {
+                format!("expired {}s ago", (now - expiry).as_secs())
+            };
+            let _ = writeln!(output, "{}: {}\n{}", cache_key, remaining, dns);
+        }
+    });
+    if output.is_empty() {
+        Err(format!("Not cached: {}", name))
+    } else {
+        Ok(output)
+    }
+}
+
+fn stats(cache: &ShardedCache<CacheKey, Dns>) -> CommandResult {
+    let stats = cache.stats();
+    let mut output = String::new();
+    let _ = writeln!(output, "entries: {}", cache.len());
+    let _ = writeln!(output, "bytes: {}", cache.bytes());
+    let _ = writeln!(output, "hits: {}", stats.hits);
+    let _ = writeln!(output, "misses: {}", stats.misses);
+    let _ = writeln!(output, "hit ratio: {:.2}%", stats.hit_ratio() * 100.0);
+    let _ = writeln!(output, "evictions: {}", stats.evictions);
+    Ok(output)
+}
+
+/// Execute a `cache` command.
+pub(super) fn execute(args: &[&str], context: &Context) -> CommandResult {
+    let cache = match &context.cache {
+        Some(cache) => cache,
+        None => return Err("The cache is disabled".to_string()),
+    };
+    match args {
+        ["flush"] => Ok(format!("Removed {} entries\n", cache.clear())),
+        ["flush", name] => {
+            let name = parse_domain_name(name)?;
+            flush(cache, |domain_name| domain_name == name)
+        }
+        ["flush-suffix", suffix] => {
+            let suffix = parse_domain_name(suffix)?;
+            flush(cache, |domain_name| is_subdomain(domain_name, &suffix))
+        }
+        ["lookup", name] => lookup(cache, name, None),
+        ["lookup", name, qtype] => lookup(cache, name, Some(qtype)),
+        ["stats"] => stats(cache),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CacheKey, ShardedCache};
+
+    use dns_message_parser::{Dns, DomainName, RCode, Type};
+
+    use std::convert::TryFrom;
+    use std::time::Duration;
+
+    use super::{flush, lookup, parse_domain_name};
+    use crate::dns::{create_question, create_request, create_response};
+    use crate::forward::is_subdomain;
+
+    fn create_cache() -> ShardedCache<CacheKey, Dns> {
+        let cache = ShardedCache::new(1024, None, 0);
+        let d = Duration::from_secs(60);
+        for (domain_name, qtype) in &[
+            ("example.com", Type::A),
+            ("example.com", Type::AAAA),
+            ("www.Example.com", Type::A),
+            ("example.org", Type::A),
+        ] {
+            let domain_name = DomainName::try_from(*domain_name).unwrap();
+            let dns_request = create_request(create_question(domain_name, qtype.clone()));
+            let question = dns_request.questions[0].clone();
+            let cache_key = CacheKey::new(0, question, &dns_request.flags, None);
+            let dns = create_response(&dns_request, RCode::NoError);
+            cache.lock(&cache_key).put(cache_key, dns, 0, d);
+        }
+        cache
+    }
+
+    #[test]
+    fn test_parse_domain_name() {
+        assert_eq!(parse_domain_name("Example.COM").unwrap(), "example.com.");
+        assert_eq!(parse_domain_name(".").unwrap(), ".");
+        assert!(parse_domain_name("example..com").is_err());
+    }
+
+    #[test]
+    fn test_flush() {
+        let cache = create_cache();
+        let name = parse_domain_name("example.com").unwrap();
+        assert!(flush(&cache, |domain_name| domain_name == name).is_ok());
+        assert_eq!(cache.len(), 2);
+
+        let cache = create_cache();
+        assert!(flush(&cache, |domain_name| is_subdomain(domain_name, &name)).is_ok());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let cache = create_cache();
+        let output = lookup(&cache, "example.com", None).unwrap();
+        assert!(output.contains("expires in"));
+        assert!(lookup(&cache, "www.example.com", Some("a")).is_ok());
+        assert!(lookup(&cache, "www.example.com", Some("AAAA")).is_err());
+        assert!(lookup(&cache, "example.net", None).is_err());
+    }
+}
@@ -0,0 +1,28 @@
+use crate::{DohError, DohResult};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+use super::{ERROR, OK};
+
+/// Send the command `args` to the control socket `socket` of a running `doh-client`.
+///
+/// Returns the output of the command or `DohError::Control` with the error message of the
+/// server.
+pub async fn ctl(socket: &str, args: &[&str]) -> DohResult<String> {
+    let mut stream = UnixStream::connect(socket).await?;
+    let command = format!("{}\n", args.join(" "));
+    stream.write_all(command.as_bytes()).await?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    let (status, output) = match response.find('\n') {
+        Some(i) => (&response[..i], &response[i + 1..]),
+        None => (response.as_str(), ""),
+    };
+    match status {
+        OK => Ok(output.to_string()),
+        ERROR => Err(DohError::Control(output.trim_end().to_string())),
+        _ => Err(DohError::Control(format!("Invalid response: {}", response))),
+    }
+}
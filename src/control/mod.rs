@@ -0,0 +1,37 @@
+//! The control socket, which is used to administrate a running `doh-client`.
+//!
+//! The socket is only accessible by its owner and commands are only accepted from root and the
+//! user of the `doh-client`. The protocol is line based: The client sends one command, which
+//! consists of words separated by whitespace, and a newline. The server answers with `ok` or
+//! `error` on the first line, followed by the output of the command, and closes the connection.
+
+use std::time::Duration;
+
+mod cache;
+
+mod upstream;
+
+mod client;
+pub use client::ctl;
+
+mod server;
+pub(crate) use server::control_handler;
+
+/// The first line of the response, if the command succeeded.
+const OK: &str = "ok";
+
+/// The first line of the response, if the command failed.
+const ERROR: &str = "error";
+
+/// The maximum length of a command in bytes.
+const MAX_COMMAND_LENGTH: u64 = 4096;
+
+/// The time, in which the client has to send the command, so that an idle connection does not
+/// stay open forever.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The default path of the control socket.
+pub const DEFAULT_CONTROL_SOCKET: &str = "/run/doh-client.sock";
+
+/// The result of a command, which contains the output or the error message.
+type CommandResult = Result<String, String>;
@@ -0,0 +1,105 @@
+use crate::logger::{get_log_level, set_log_level};
+use crate::Context;
+
+use libc::geteuid;
+
+use log::LevelFilter;
+
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::net::Shutdown;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::spawn;
+use tokio::time::timeout;
+
+use super::{cache, upstream, CommandResult, ERROR, MAX_COMMAND_LENGTH, OK, READ_TIMEOUT};
+
+const USAGE: &str =
+    "Usage: status | reconnect | switch ADDRS | resolve | reload | log [LEVEL|reset] | cache ...";
+
+fn log(args: &[&str]) -> CommandResult {
+    match args {
+        [] => {}
+        ["reset"] => set_log_level(None),
+        [level] => {
+            let level: LevelFilter = level
+                .parse()
+                .map_err(|_| format!("Invalid log level: {}", level))?;
+            set_log_level(Some(level));
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+    match get_log_level() {
+        Some(level) => Ok(format!("Log level: {}\n", level)),
+        None => Ok("Log level: RUST_LOG\n".to_string()),
+    }
+}
+
+async fn execute(args: &[&str], context: &Context) -> CommandResult {
+    match args {
+        ["status"] => upstream::status(context).await,
+        ["reconnect"] => upstream::reconnect(context).await,
+        ["switch", remote_addrs] => upstream::switch(context, remote_addrs).await,
+        ["resolve"] => upstream::resolve(context).await,
+        ["reload"] => upstream::reload(context),
+        ["log", args @ ..] => log(args),
+        ["cache", args @ ..] => cache::execute(args, context),
+        [] => Err("No command".to_string()),
+        _ => Err(format!("Unknown command: {}\n{}", args.join(" "), USAGE)),
+    }
+}
+
+/// Check if the peer is allowed to send commands, which are only accepted from root and the user
+/// of the `doh-client`.
+fn is_permitted(stream: &UnixStream) -> IoResult<bool> {
+    let ucred = stream.peer_cred()?;
+    // geteuid() is always successful.
+    let euid = unsafe { geteuid() };
+    Ok(ucred.uid == 0 || ucred.uid == euid)
+}
+
+async fn connection_handler(mut stream: UnixStream, context: &Context) -> IoResult<()> {
+    if !is_permitted(&stream)? {
+        warn!(
+            "Reject control connection from uid {}",
+            stream.peer_cred()?.uid
+        );
+        let response = format!("{}\nPermission denied\n", ERROR);
+        stream.write_all(response.as_bytes()).await?;
+        return stream.shutdown(Shutdown::Both);
+    }
+
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader.take(MAX_COMMAND_LENGTH));
+    let mut command = String::new();
+    match timeout(READ_TIMEOUT, reader.read_line(&mut command)).await {
+        Ok(result) => result?,
+        Err(_) => return Err(IoError::new(ErrorKind::TimedOut, "No command received")),
+    };
+    let args: Vec<&str> = command.split_whitespace().collect();
+    debug!("Control command: {:?}", args);
+
+    let response = match execute(&args, context).await {
+        Ok(output) => format!("{}\n{}", OK, output),
+        Err(e) => format!("{}\n{}\n", ERROR, e),
+    };
+    writer.write_all(response.as_bytes()).await?;
+    writer.shutdown().await
+}
+
+/// Accept the connections of the control socket and execute their commands.
+pub(crate) async fn control_handler(mut listener: UnixListener, context: &'static Context) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                spawn(async move {
+                    if let Err(e) = connection_handler(stream, context).await {
+                        error!("Could not handle control connection: {}", e);
+                    }
+                });
+            }
+            Err(e) => error!("Could not accept control connection: {}", e),
+        }
+    }
+}
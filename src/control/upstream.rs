@@ -0,0 +1,129 @@
+use crate::client_cert::{reload_client_cert, ClientCert};
+use crate::remote::Upstream;
+use crate::Context;
+
+use std::fmt::Write;
+use std::net::SocketAddr;
+
+use super::CommandResult;
+
+/// Parse the comma-separated socket addresses `remote_addrs`.
+fn parse_remote_addrs(remote_addrs: &str) -> Result<Vec<SocketAddr>, String> {
+    remote_addrs
+        .split(',')
+        .map(|remote_addr| {
+            remote_addr
+                .parse()
+                .map_err(|e| format!("Invalid address {}: {}", remote_addr, e))
+        })
+        .collect()
+}
+
+/// Show the upstreams and their connection state.
+pub(super) async fn status(context: &Context) -> CommandResult {
+    let mut output = String::new();
+    for (i, upstream) in context.upstreams.iter().enumerate() {
+        let mut guard_upstream = upstream.lock().await;
+        let _ = write!(output, "upstream {}: {}", i, *guard_upstream);
+        if let Upstream::Doh(session) = &mut *guard_upstream {
+            let state = if session.is_connected() {
+                "connected"
+            } else {
+                "disconnected"
+            };
+            let _ = write!(
+                output,
+                " ({}, connection {})",
+                state,
+                session.connection_id()
+            );
+        }
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+/// Close the connection to the DoH server and connect again.
+pub(super) async fn reconnect(context: &Context) -> CommandResult {
+    let resolve = context.upstreams[0].lock().await.resolve();
+    resolve.await;
+    let mut guard_upstream = context.upstreams[0].lock().await;
+    if let Upstream::Doh(session) = &mut *guard_upstream {
+        session.force_reconnect().await.map_err(|e| e.to_string())?;
+        Ok(format!("Connection {}\n", session.connection_id()))
+    } else {
+        Err("The upstream is not a DoH server".to_string())
+    }
+}
+
+/// Connect to the DoH server via the addresses `remote_addrs`.
+pub(super) async fn switch(context: &Context, remote_addrs: &str) -> CommandResult {
+    let remote_addrs = parse_remote_addrs(remote_addrs)?;
+    let mut guard_upstream = context.upstreams[0].lock().await;
+    if let Upstream::Doh(session) = &mut *guard_upstream {
+        session.switch(remote_addrs);
+        Ok(format!("{}\n", session))
+    } else {
+        Err("The upstream is not a DoH server".to_string())
+    }
+}
+
+/// Resolve the addresses of the DoH server again.
+pub(super) async fn resolve(context: &Context) -> CommandResult {
+    let guard_upstream = context.upstreams[0].lock().await;
+    let resolve_again = if let Upstream::Doh(session) = &*guard_upstream {
+        session.resolve_again()
+    } else {
+        return Err("The upstream is not a DoH server".to_string());
+    };
+    // The upstream is not locked while the hostname is resolved.
+    drop(guard_upstream);
+    match resolve_again {
+        Some(resolve_again) => match resolve_again.await {
+            Ok(remote_addrs) => Ok(format!("Resolved {:?}\n", remote_addrs)),
+            Err(e) => Err(e.to_string()),
+        },
+        None => Err("No bootstrap resolver is used".to_string()),
+    }
+}
+
+fn reload_with(client_cert: Option<&ClientCert>) -> CommandResult {
+    match client_cert {
+        Some(client_cert) => match reload_client_cert(client_cert) {
+            Ok(()) => Ok("Reloaded the client certificate\n".to_string()),
+            Err(e) => Err(e.to_string()),
+        },
+        None => Err("No client certificate is used".to_string()),
+    }
+}
+
+/// Read the client certificate and its private key again, like on SIGHUP. New connections use the
+/// new certificate.
+pub(super) fn reload(context: &Context) -> CommandResult {
+    reload_with(context.client_cert.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_remote_addrs, reload_with};
+    use crate::client_cert::ClientCert;
+
+    const CLIENT_CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs/client.pem");
+    const CLIENT_KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs/client.key");
+
+    #[test]
+    fn test_parse_remote_addrs() {
+        let remote_addrs = parse_remote_addrs("1.1.1.1:443,[2606:4700:4700::1111]:443").unwrap();
+        assert_eq!(remote_addrs.len(), 2);
+        assert!(remote_addrs[1].is_ipv6());
+        assert!(parse_remote_addrs("1.1.1.1").is_err());
+        assert!(parse_remote_addrs("").is_err());
+    }
+
+    #[test]
+    fn test_reload() {
+        let client_cert = ClientCert::new(CLIENT_CERT, CLIENT_KEY).unwrap();
+        assert!(reload_with(Some(&client_cert)).is_ok());
+        assert!(reload_with(None).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+//! A minimal DER reader and writer for the parts of certificates and keys, which are not parsed
+//! by `rustls` (see X.690).
+
+/// The DER tag of an INTEGER.
+pub(crate) const INTEGER: u8 = 0x02;
+/// The DER tag of an OCTET STRING.
+pub(crate) const OCTET_STRING: u8 = 0x04;
+/// The DER tag of an OBJECT IDENTIFIER.
+pub(crate) const OBJECT_IDENTIFIER: u8 = 0x06;
+/// The DER tag of a SEQUENCE.
+pub(crate) const SEQUENCE: u8 = 0x30;
+
+/// Read the DER element at the beginning of `input`.
+///
+/// Returns the tag, the content and the rest of `input`.
+pub(crate) fn read_der(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&length, mut input) = input.split_first()?;
+    let length = if length < 0x80 {
+        length as usize
+    } else {
+        let n = (length & 0x7f) as usize;
+        if n == 0 || n > 4 || input.len() < n {
+            return None;
+        }
+        let length = input[..n]
+            .iter()
+            .fold(0, |length, b| (length << 8) | *b as usize);
+        input = &input[n..];
+        length
+    };
+    if input.len() < length {
+        return None;
+    }
+    Some((tag, &input[..length], &input[length..]))
+}
+
+/// Write a DER element with the tag `tag` and the content `content`.
+pub(crate) fn write_der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(content.len() + 6);
+    output.push(tag);
+    let length = content.len();
+    if length < 0x80 {
+        output.push(length as u8);
+    } else {
+        let bytes = length.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        output.push(0x80 | (bytes.len() - skip) as u8);
+        output.extend_from_slice(&bytes[skip..]);
+    }
+    output.extend_from_slice(content);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_der, write_der, OCTET_STRING, SEQUENCE};
+
+    #[test]
+    fn test_der() {
+        let content = vec![0x55; 300];
+        let element = write_der(OCTET_STRING, &content);
+        assert_eq!(&element[..4], &[OCTET_STRING, 0x82, 0x01, 0x2c]);
+        assert_eq!(
+            read_der(&element),
+            Some((OCTET_STRING, &content[..], &[][..]))
+        );
+
+        let element = write_der(SEQUENCE, &[0x01, 0x02]);
+        assert_eq!(element, vec![SEQUENCE, 0x02, 0x01, 0x02]);
+        assert_eq!(read_der(&element[..3]), None);
+        assert_eq!(read_der(&[SEQUENCE, 0x80]), None);
+    }
+}
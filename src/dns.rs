@@ -0,0 +1,87 @@
+use dns_message_parser::{
+    Class, Dns, DomainName, Flags, Opcode, QClass, QType, Question, RCode, Type,
+};
+
+#[cfg(test)]
+use std::convert::TryFrom;
+
+/// Create the question for the records of the type `type_` of `domain_name` in the class IN.
+pub(crate) fn create_question(domain_name: DomainName, type_: Type) -> Question {
+    Question::new(domain_name, QClass::Class(Class::IN), QType::Type(type_))
+}
+
+/// Create a recursive query with the ID 0, which contains only the question `question`.
+pub(crate) fn create_request(question: Question) -> Dns {
+    Dns {
+        id: 0,
+        flags: Flags {
+            qr: false,
+            opcode: Opcode::Query,
+            aa: false,
+            tc: false,
+            rd: true,
+            ra: false,
+            ad: false,
+            cd: false,
+            rcode: RCode::NoError,
+        },
+        questions: vec![question],
+        answers: Vec::new(),
+        authorities: Vec::new(),
+        additionals: Vec::new(),
+    }
+}
+
+/// Create a response without records with the `rcode` for the request `dns_request`.
+///
+/// The ID, the opcode, the RD and CD flags and the questions are taken from the request.
+pub(crate) fn create_response(dns_request: &Dns, rcode: RCode) -> Dns {
+    Dns {
+        id: dns_request.id,
+        flags: Flags {
+            qr: true,
+            opcode: dns_request.flags.opcode.clone(),
+            aa: false,
+            tc: false,
+            rd: dns_request.flags.rd,
+            ra: true,
+            ad: false,
+            cd: dns_request.flags.cd,
+            rcode,
+        },
+        questions: dns_request.questions.clone(),
+        answers: Vec::new(),
+        authorities: Vec::new(),
+        additionals: Vec::new(),
+    }
+}
+
+/// Create the query for the A records of `domain_name`, which is used by the tests.
+#[cfg(test)]
+pub(crate) fn create_test_request(domain_name: &str) -> Dns {
+    let domain_name = DomainName::try_from(domain_name).unwrap();
+    create_request(create_question(domain_name, Type::A))
+}
+
+#[cfg(test)]
+mod tests {
+    use dns_message_parser::RCode;
+
+    use super::{create_response, create_test_request};
+
+    #[test]
+    fn test_create_response() {
+        let mut dns_request = create_test_request("example.com");
+        dns_request.id = 1234;
+        dns_request.flags.cd = true;
+
+        let dns_response = create_response(&dns_request, RCode::ServFail);
+        assert!(dns_response.is_response());
+        assert_eq!(dns_response.id, 1234);
+        assert_eq!(dns_response.flags.rcode, RCode::ServFail);
+        assert!(dns_response.flags.rd);
+        assert!(dns_response.flags.cd);
+        assert_eq!(dns_response.questions, dns_request.questions);
+        assert!(dns_response.answers.is_empty());
+    }
+}
@@ -0,0 +1,245 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::DohError;
+
+use dns_message_parser::{Dns, RData};
+
+/// The type of the OPT pseudo resource record (see RFC 6891).
+const OPT_TYPE: u16 = 41;
+
+/// The UDP payload size, which is advertised in the OPT record.
+const UDP_PAYLOAD_SIZE: u16 = 1232;
+
+/// The option code of the Extended DNS Error option (see RFC 8914).
+const EDE_OPTION_CODE: u16 = 15;
+
+/// The option code of the Client Subnet option (see RFC 7871).
+const ECS_OPTION_CODE: u16 = 8;
+
+/// The DO bit in the TTL field of the OPT record (see RFC 3225).
+const DO_BIT: u32 = 0x8000;
+
+/// The length of the DNS header.
+const HEADER_LENGTH: usize = 12;
+
+/// The offset of the ARCOUNT field in the DNS header.
+const ARCOUNT_OFFSET: usize = 10;
+
+/// The maximum length of the EXTRA-TEXT of an Extended DNS Error, so that a response without
+/// records stays far below 512 bytes.
+const MAX_EXTRA_TEXT_LENGTH: usize = 64;
+
+/// The INFO-CODEs of the Extended DNS Errors (see RFC 8914 section 4).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ExtendedError {
+    Other = 0,
+    StaleAnswer = 3,
+    NotSupported = 21,
+    NoReachableAuthority = 22,
+    NetworkError = 23,
+}
+
+impl From<&DohError> for ExtendedError {
+    fn from(e: &DohError) -> Self {
+        match e {
+            DohError::CircuitOpen
+            | DohError::CouldNotConnect(_)
+            | DohError::CouldNotResolve(_)
+            | DohError::IsNotConnected => ExtendedError::NoReachableAuthority,
+            DohError::Timeout | DohError::Io(_) | DohError::H2(_) => ExtendedError::NetworkError,
+            #[cfg(feature = "socks5")]
+            DohError::Socks(_) => ExtendedError::NetworkError,
+            DohError::InFlight(e) => ExtendedError::from(e.as_ref()),
+            _ => ExtendedError::Other,
+        }
+    }
+}
+
+/// Check if the DNS message contains an OPT record, which means the client supports EDNS.
+pub(crate) fn has_opt(dns: &Dns) -> bool {
+    dns.additionals
+        .iter()
+        .any(|rr| matches!(rr.get_rdata(), RData::OPT))
+}
+
+/// The EDNS data of a request, which changes the response of the server.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Edns {
+    /// The DO bit (see RFC 3225).
+    pub(crate) dnssec_ok: bool,
+    /// The family, source prefix length and address of the Client Subnet option (see RFC 7871).
+    pub(crate) client_subnet: Option<Vec<u8>>,
+}
+
+fn read_u16(msg: &[u8], offset: usize) -> Option<u16> {
+    let bytes = msg.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(msg: &[u8], offset: usize) -> Option<u32> {
+    let bytes = msg.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Get the offset after the domain name, which starts at `offset`.
+fn skip_domain_name(msg: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let length = *msg.get(offset)?;
+        if length & 0xc0 == 0xc0 {
+            // A compression pointer is always the end of the domain name.
+            return Some(offset + 2);
+        } else if length == 0 {
+            return Some(offset + 1);
+        }
+        offset += 1 + length as usize;
+    }
+}
+
+fn get_client_subnet(mut options: &[u8]) -> Option<Vec<u8>> {
+    while !options.is_empty() {
+        let code = read_u16(options, 0)?;
+        let length = read_u16(options, 2)? as usize;
+        let data = options.get(4..4 + length)?;
+        if code == ECS_OPTION_CODE {
+            // The scope prefix length is skipped, because it is only set in responses.
+            let mut client_subnet = data.get(..3)?.to_vec();
+            client_subnet.extend_from_slice(data.get(4..)?);
+            return Some(client_subnet);
+        }
+        options = &options[4 + length..];
+    }
+    None
+}
+
+/// Get the EDNS data of the encoded DNS message `msg`.
+///
+/// The message is parsed by hand, because the DO bit and the options of OPT records are not
+/// supported by `dns_message_parser`. Returns `None` if the message does not contain an OPT
+/// record.
+pub(crate) fn get_edns(msg: &[u8]) -> Option<Edns> {
+    let qdcount = read_u16(msg, 4)?;
+    let mut rrcount = 0;
+    for offset in &[6, 8, ARCOUNT_OFFSET] {
+        rrcount += read_u16(msg, *offset)? as usize;
+    }
+
+    let mut offset = HEADER_LENGTH;
+    for _ in 0..qdcount {
+        // The domain name, type and class.
+        offset = skip_domain_name(msg, offset)? + 4;
+    }
+    for _ in 0..rrcount {
+        // The domain name, type, class, TTL, length and data.
+        offset = skip_domain_name(msg, offset)?;
+        let type_ = read_u16(msg, offset)?;
+        let ttl = read_u32(msg, offset + 4)?;
+        let rdlength = read_u16(msg, offset + 8)? as usize;
+        offset += 10;
+        if type_ == OPT_TYPE {
+            let rdata = msg.get(offset..offset + rdlength)?;
+            return Some(Edns {
+                dnssec_ok: ttl & DO_BIT != 0,
+                client_subnet: get_client_subnet(rdata),
+            });
+        }
+        offset += rdlength;
+    }
+    None
+}
+
+/// Append an OPT record with an Extended DNS Error option to the encoded DNS message `msg`.
+///
+/// The OPT record is encoded by hand, because the options of OPT records are not supported by
+/// `dns_message_parser`. The message must not contain an OPT record already. The `text` is
+/// truncated to `MAX_EXTRA_TEXT_LENGTH` bytes.
+pub(crate) fn add_extended_error(msg: Bytes, extended_error: ExtendedError, text: &str) -> Bytes {
+    let mut length = text.len().min(MAX_EXTRA_TEXT_LENGTH);
+    while !text.is_char_boundary(length) {
+        length -= 1;
+    }
+    let text = &text.as_bytes()[..length];
+    let option_length = 2 + text.len();
+    let mut bytes = BytesMut::with_capacity(msg.len() + 15 + option_length);
+    bytes.extend_from_slice(&msg);
+
+    let arcount = u16::from_be_bytes([bytes[ARCOUNT_OFFSET], bytes[ARCOUNT_OFFSET + 1]]);
+    let arcount = (arcount + 1).to_be_bytes();
+    bytes[ARCOUNT_OFFSET..ARCOUNT_OFFSET + 2].copy_from_slice(&arcount);
+
+    // The root domain name, type, UDP payload size and the extended RCODE and flags.
+    bytes.put_u8(0);
+    bytes.put_u16(OPT_TYPE);
+    bytes.put_u16(UDP_PAYLOAD_SIZE);
+    bytes.put_u32(0);
+    bytes.put_u16((4 + option_length) as u16);
+    bytes.put_u16(EDE_OPTION_CODE);
+    bytes.put_u16(option_length as u16);
+    bytes.put_u16(extended_error as u16);
+    bytes.put_slice(text);
+    bytes.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use dns_message_parser::Dns;
+
+    use super::{add_extended_error, get_edns, has_opt, ExtendedError, MAX_EXTRA_TEXT_LENGTH};
+
+    const QUERY: [u8; 29] = [
+        0x12, 0x34, 0x01, 0x20, 0, 1, 0, 0, 0, 0, 0, 1, 7, b'e', b'x', b'a', b'm', b'p', b'l',
+        b'e', 3, b'c', b'o', b'm', 0, 0, 1, 0, 1,
+    ];
+
+    #[test]
+    fn test_get_edns() {
+        let mut msg = QUERY.to_vec();
+        msg.extend_from_slice(&[0, 0, 41, 0x10, 0, 0, 0, 0x80, 0, 0, 11]);
+        // The Client Subnet option with 192.0.2.0/24.
+        msg.extend_from_slice(&[0, 8, 0, 7, 0, 1, 24, 0, 192, 0, 2]);
+        let edns = get_edns(&msg).unwrap();
+        assert!(edns.dnssec_ok);
+        assert_eq!(edns.client_subnet, Some(vec![0, 1, 24, 192, 0, 2]));
+
+        let mut msg = QUERY.to_vec();
+        msg.extend_from_slice(&[0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+        let edns = get_edns(&msg).unwrap();
+        assert!(!edns.dnssec_ok);
+        assert_eq!(edns.client_subnet, None);
+
+        let mut msg = QUERY.to_vec();
+        msg[11] = 0;
+        assert_eq!(get_edns(&msg), None);
+        assert_eq!(get_edns(&QUERY[..20]), None);
+    }
+
+    #[test]
+    fn test_add_extended_error() {
+        let header = [0x12, 0x34, 0x81, 0x82, 0, 0, 0, 0, 0, 0, 0, 0];
+        let msg = Bytes::copy_from_slice(&header);
+        let msg = add_extended_error(msg, ExtendedError::NetworkError, "Timeout");
+        assert_eq!(&msg[..10], &header[..10]);
+        assert_eq!(&msg[10..12], &[0, 1]);
+        assert_eq!(
+            &msg[12..],
+            &[
+                0, 0, 41, 0x04, 0xd0, 0, 0, 0, 0, 0, 13, 0, 15, 0, 9, 0, 23, b'T', b'i', b'm',
+                b'e', b'o', b'u', b't'
+            ][..]
+        );
+        assert!(has_opt(&Dns::decode(&msg).unwrap()));
+    }
+
+    #[test]
+    fn test_add_extended_error_truncated() {
+        let header = [0x12, 0x34, 0x81, 0x82, 0, 0, 0, 0, 0, 0, 0, 0];
+        let msg = Bytes::copy_from_slice(&header);
+        // The text is truncated before the multi-byte character at the end.
+        let text = format!("{}\u{e4}", "a".repeat(MAX_EXTRA_TEXT_LENGTH - 1));
+        let msg = add_extended_error(msg, ExtendedError::Other, &text);
+        assert_eq!(msg.len(), header.len() + 17 + MAX_EXTRA_TEXT_LENGTH - 1);
+        assert_eq!(&msg[msg.len() - 1], &b'a');
+        assert!(has_opt(&Dns::decode(&msg).unwrap()));
+    }
+}
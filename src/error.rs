@@ -11,9 +11,15 @@ use http::{HeaderValue, StatusCode};
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::io::Error as IoError;
 use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::time::Elapsed;
 #[cfg(feature = "socks5")]
 use tokio_socks::Error as SocksError;
 
+use webpki::Error as WebpkiError;
+
+#[derive(Debug)]
 pub enum Error {
     Io(IoError),
     H2(H2Error),
@@ -23,15 +29,29 @@ pub enum Error {
     #[cfg(feature = "socks5")]
     Socks(SocksError),
     IsNotConnected,
-    PEMParser,
+    File(String, IoError),
+    PEMParser(String),
+    PrivateKey(String),
+    Certificate(String, usize, WebpkiError),
+    NoCaCertificates,
+    PinMismatch(Vec<String>),
+    BackupPinWithoutPin,
     CacheSize,
+    CacheTtl,
     CouldNotConnect(Vec<SocketAddr>),
+    CircuitOpen,
+    CouldNotResolve(String),
     CouldNotGetResponse(Dns),
+    InFlight(Arc<Error>),
+    Control(String),
     HeaderStatus(StatusCode),
     HeaderContentType(HeaderValue),
     HeaderNoContentType,
     DnsNotRequest(Dns),
     DnsNotResponse(Dns),
+    DnsMismatch(Dns),
+    Random,
+    Timeout,
 }
 
 impl From<IoError> for Error {
@@ -40,6 +60,12 @@ impl From<IoError> for Error {
     }
 }
 
+impl From<Elapsed> for Error {
+    fn from(_: Elapsed) -> Self {
+        Error::Timeout
+    }
+}
+
 impl From<H2Error> for Error {
     fn from(e: H2Error) -> Self {
         Error::H2(e)
@@ -73,6 +99,45 @@ impl From<SocksError> for Error {
     }
 }
 
+impl Error {
+    /// Get the name of the variant, which is used to group the errors.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Error::Io(_) => "Io",
+            Error::H2(_) => "H2",
+            Error::Decode(_) => "Decode",
+            Error::Encode(_) => "Encode",
+            Error::TrySend(_) => "TrySend",
+            #[cfg(feature = "socks5")]
+            Error::Socks(_) => "Socks",
+            Error::IsNotConnected => "IsNotConnected",
+            Error::File(_, _) => "File",
+            Error::PEMParser(_) => "PEMParser",
+            Error::PrivateKey(_) => "PrivateKey",
+            Error::Certificate(_, _, _) => "Certificate",
+            Error::NoCaCertificates => "NoCaCertificates",
+            Error::PinMismatch(_) => "PinMismatch",
+            Error::BackupPinWithoutPin => "BackupPinWithoutPin",
+            Error::CacheSize => "CacheSize",
+            Error::CacheTtl => "CacheTtl",
+            Error::CouldNotConnect(_) => "CouldNotConnect",
+            Error::CircuitOpen => "CircuitOpen",
+            Error::CouldNotResolve(_) => "CouldNotResolve",
+            Error::CouldNotGetResponse(_) => "CouldNotGetResponse",
+            Error::InFlight(e) => e.kind(),
+            Error::Control(_) => "Control",
+            Error::HeaderStatus(_) => "HeaderStatus",
+            Error::HeaderContentType(_) => "HeaderContentType",
+            Error::HeaderNoContentType => "HeaderNoContentType",
+            Error::DnsNotRequest(_) => "DnsNotRequest",
+            Error::DnsNotResponse(_) => "DnsNotResponse",
+            Error::DnsMismatch(_) => "DnsMismatch",
+            Error::Random => "Random",
+            Error::Timeout => "Timeout",
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         match self {
@@ -84,17 +149,39 @@ impl Display for Error {
             #[cfg(feature = "socks5")]
             Error::Socks(e) => write!(f, "Socks Error: {}", e),
             Error::IsNotConnected => write!(f, "doh-client is not connected"),
-            Error::PEMParser => write!(f, "Cannot parse pem file"),
+            Error::File(path, e) => write!(f, "Cannot read {}: {}", path, e),
+            Error::PEMParser(path) => write!(f, "Cannot parse pem file {}", path),
+            Error::PrivateKey(path) => {
+                write!(f, "Cannot find a supported private key in {}", path)
+            }
+            Error::Certificate(path, i, e) => {
+                write!(f, "Certificate {} of {} is invalid: {}", i, path, e)
+            }
+            Error::NoCaCertificates => write!(f, "No CA certificates were found"),
+            Error::PinMismatch(pins) => write!(
+                f,
+                "No pin matches the certificate chain of the server: got {}",
+                pins.join(", ")
+            ),
+            Error::BackupPinWithoutPin => write!(f, "Backup pins are given without a pin"),
             Error::CacheSize => write!(
                 f,
-                "Cache size is zero and cache fallback is enabled simultaneously"
+                "Cache size is zero and cache fallback or cache file is enabled simultaneously"
             ),
+            Error::CacheTtl => write!(f, "Minimum TTL is greater than the maximum TTL"),
             Error::CouldNotConnect(remote_addrs) => {
                 write!(f, "Could not connect to any address: {:?}", remote_addrs)
             }
+            Error::CircuitOpen => write!(
+                f,
+                "Circuit breaker is open, the remote server was not reachable recently"
+            ),
+            Error::CouldNotResolve(hostname) => write!(f, "Could not resolve {}", hostname),
             Error::CouldNotGetResponse(dns_request) => {
                 write!(f, "Could not get response for: {:?}", dns_request)
             }
+            Error::InFlight(e) => write!(f, "Identical request failed: {}", e),
+            Error::Control(e) => write!(f, "Control command failed: {}", e),
             Error::HeaderStatus(status) => write!(f, "Header status: got {}", status),
             Error::HeaderContentType(content_type) => write!(
                 f,
@@ -104,6 +191,11 @@ impl Display for Error {
             Error::HeaderNoContentType => write!(f, "Header content type is missing"),
             Error::DnsNotRequest(dns) => write!(f, "DNS packet is not a request: {:?}", dns),
             Error::DnsNotResponse(dns) => write!(f, "DNS packet is not a response: {:?}", dns),
+            Error::DnsMismatch(dns) => {
+                write!(f, "DNS response does not match the request: {:?}", dns)
+            }
+            Error::Random => write!(f, "Could not generate a random number"),
+            Error::Timeout => write!(f, "Timeout"),
         }
     }
 }
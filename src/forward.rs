@@ -0,0 +1,131 @@
+use dns_message_parser::{Dns, DomainError, DomainName};
+
+use std::cmp::Reverse;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::net::SocketAddr;
+
+/// A conditional forwarding rule.
+///
+/// All queries for the domain `suffix` and its subdomains are forwarded to the plain DNS servers
+/// `remote_addrs` instead of the DoH server.
+pub struct Rule {
+    pub(crate) suffix: String,
+    pub(crate) remote_addrs: Vec<SocketAddr>,
+}
+
+impl Rule {
+    /// Create a new `doh_client::ForwardRule` object.
+    ///
+    /// A leading `*.` in `suffix` is ignored, so `*.consul` and `consul` are the same rule.
+    pub fn new(suffix: &str, remote_addrs: Vec<SocketAddr>) -> Result<Rule, DomainError> {
+        let suffix = suffix.strip_prefix("*.").unwrap_or(suffix);
+        let suffix = if suffix.is_empty() || suffix == "." {
+            DomainName::default()
+        } else {
+            DomainName::try_from(suffix)?
+        };
+        Ok(Rule {
+            suffix: suffix.to_string().to_lowercase(),
+            remote_addrs,
+        })
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{} -> {:?}", self.suffix, self.remote_addrs)
+    }
+}
+
+/// Check if `domain_name` is `suffix` or one of its subdomains.
+///
+/// Both names have to be in lowercase, because domain names are compared case-insensitively.
+pub(crate) fn is_subdomain(domain_name: &str, suffix: &str) -> bool {
+    if suffix == "." || domain_name == suffix {
+        true
+    } else if let Some(prefix) = domain_name.strip_suffix(suffix) {
+        prefix.ends_with('.')
+    } else {
+        false
+    }
+}
+
+/// Select the upstream for a DNS request by the domain suffix rules.
+///
+/// The upstream `0` is always the DoH server, the rule `i` is mapped to the upstream `i + 1`.
+pub(crate) struct Router {
+    suffixes: Vec<(String, usize)>,
+}
+
+impl Router {
+    pub(crate) fn new(rules: &[Rule]) -> Router {
+        let mut suffixes: Vec<(String, usize)> = rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| (rule.suffix.clone(), i + 1))
+            .collect();
+        // The longest suffix is the most specific one, so it has to be checked first.
+        suffixes.sort_by_key(|(suffix, _)| Reverse(suffix.len()));
+        Router { suffixes }
+    }
+
+    pub(crate) fn route(&self, dns_request: &Dns) -> usize {
+        if let Some(question) = dns_request.questions.first() {
+            let domain_name = question.get_domain_name().to_string().to_lowercase();
+            for (suffix, upstream) in &self.suffixes {
+                if is_subdomain(&domain_name, suffix) {
+                    return *upstream;
+                }
+            }
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_subdomain, Router, Rule};
+    use crate::dns::create_test_request;
+
+    #[test]
+    fn test_is_subdomain() {
+        assert!(is_subdomain("example.com.", "example.com."));
+        assert!(is_subdomain("www.example.com.", "example.com."));
+        assert!(is_subdomain("www.example.com.", "."));
+        assert!(!is_subdomain("wwwexample.com.", "example.com."));
+        assert!(!is_subdomain("example.org.", "example.com."));
+    }
+
+    #[test]
+    fn test_route() {
+        let rules = vec![
+            Rule::new("corp.example.com", Vec::new()).unwrap(),
+            Rule::new("*.consul", Vec::new()).unwrap(),
+            Rule::new("dev.corp.example.com", Vec::new()).unwrap(),
+        ];
+        let router = Router::new(&rules);
+
+        assert_eq!(router.route(&create_test_request("example.com")), 0);
+        assert_eq!(
+            router.route(&create_test_request("www.corp.example.com")),
+            1
+        );
+        assert_eq!(router.route(&create_test_request("web.service.consul")), 2);
+        assert_eq!(
+            router.route(&create_test_request("host.dev.corp.example.com")),
+            3
+        );
+        assert_eq!(
+            router.route(&create_test_request("WWW.Corp.Example.COM")),
+            1
+        );
+
+        let rules = vec![Rule::new("Corp.Example.COM", Vec::new()).unwrap()];
+        let router = Router::new(&rules);
+        assert_eq!(
+            router.route(&create_test_request("www.corp.example.com")),
+            1
+        );
+    }
+}
@@ -1,17 +1,35 @@
 use bytes::Bytes;
 
-use crate::{Cache, Context, DohError, DohResult};
+use crate::dns::create_response;
+use crate::edns::{add_extended_error, get_edns, has_opt, Edns, ExtendedError};
+use crate::in_flight::{set_id, Join};
+use crate::persist::{encode, write_file};
+use crate::remote::{get_ttl, ResponseFuture, Upstream};
+use crate::stale::create_stale_response;
+use crate::ttl::{copy_with_ttl, TtlLimits};
+use crate::{CacheKey, Context, DohError, DohResult, ShardedCache};
 
-use dns_message_parser::{Dns, Question};
+use dns_message_parser::{Dns, Opcode, RCode};
 
 use futures::channel::mpsc::UnboundedSender;
+use futures::future::{select, Either};
 use futures::lock::Mutex;
 
-use tokio::time::timeout as create_timeout;
+use tokio::spawn;
+use tokio::time::{delay_for, timeout as create_timeout};
 
-use std::future::Future;
+use std::convert::TryFrom;
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// The number of times a request is sent again if no response was received.
+const MAX_RETRIES: u32 = 2;
+
+/// The share of the remaining time, which an attempt may use, if it is not the last one.
+///
+/// Most of the time is given to the first attempt, because a slow response is more likely than a
+/// lost one. A failed attempt usually returns early and leaves the time to the retries.
+const ATTEMPT_SHARE: f64 = 0.75;
 
 fn send_response(
     dns_response: &mut Dns,
@@ -25,25 +43,68 @@ fn send_response(
     Ok(())
 }
 
+/// Send a response with the error `rcode` for the request.
+///
+/// If the client supports EDNS, then the response contains an Extended DNS Error, which explains
+/// the cause of the error.
+fn send_error_response(
+    dns_request: &Dns,
+    rcode: RCode,
+    extended_error: ExtendedError,
+    text: &str,
+    addr: SocketAddr,
+    sender: &UnboundedSender<(Bytes, SocketAddr)>,
+) -> DohResult<()> {
+    let dns_response = create_response(dns_request, rcode);
+    let mut bytes = dns_response.to_bytes()?;
+    if has_opt(dns_request) {
+        bytes = add_extended_error(bytes, extended_error, text);
+    }
+    sender.unbounded_send((bytes, addr))?;
+    Ok(())
+}
+
+/// Create a response, which contains only the header, for a request, which could not be decoded.
+///
+/// Returns `None` if the message is too short or is a response itself.
+fn create_header_error_response(msg: &[u8]) -> Option<Bytes> {
+    if msg.len() < 12 || msg[2] & 0x80 != 0 {
+        return None;
+    }
+    let opcode = (msg[2] >> 3) & 0x0f;
+    // FORMERR for queries and NOTIMP for unknown opcodes.
+    let rcode = if opcode == 0 { 1 } else { 4 };
+    let mut bytes = [0; 12];
+    bytes[..2].copy_from_slice(&msg[..2]);
+    // Keep the opcode and the RD flag, set the QR and RA flag.
+    bytes[2] = 0x80 | (msg[2] & 0x79);
+    bytes[3] = 0x80 | rcode;
+    Some(Bytes::copy_from_slice(&bytes))
+}
+
 enum CacheReturn<'a> {
     Found(DohResult<()>),
-    NotFound(Option<(&'a Mutex<Cache<Question, Dns>>, Question)>),
+    Prefetch(DohResult<()>, (&'a ShardedCache<CacheKey, Dns>, CacheKey)),
+    NotFound(Option<(&'a ShardedCache<CacheKey, Dns>, CacheKey)>),
 }
 
 async fn get_response_from_cache<'a>(
     context: &'a Context,
+    upstream: usize,
     dns_request: &Dns,
+    edns: Option<Edns>,
     addr: &SocketAddr,
 ) -> CacheReturn<'a> {
     if let Some(cache) = &context.cache {
         let questions = &dns_request.questions;
         if questions.len() == 1 {
-            let question = &questions[0];
-            let mut guard_cache = cache.lock().await;
-            let entry = if context.cache_fallback {
-                guard_cache.get_expired(question)
+            let cache_key = CacheKey::new(upstream, questions[0].clone(), &dns_request.flags, edns);
+            let mut guard_cache = cache.lock(&cache_key);
+            let age = guard_cache.age(&cache_key).unwrap_or_default().as_secs();
+            let entry = if context.serve_stale.is_some() {
+                guard_cache.get_expired(&cache_key)
             } else {
-                guard_cache.get(question)
+                guard_cache.get(&cache_key)
             };
 
             if let Some(dns_response) = entry {
@@ -51,10 +112,18 @@ async fn get_response_from_cache<'a>(
                 let sender = &context.sender;
                 let addr = *addr;
                 debug!("Question is found in cache");
-                CacheReturn::Found(send_response(dns_response, id, addr, sender))
+                // The TTLs are decremented by the time, which the response spent in the cache.
+                let age = u32::try_from(age).unwrap_or(u32::MAX);
+                let mut dns_response = copy_with_ttl(dns_response, |ttl| ttl.saturating_sub(age));
+                let result = send_response(&mut dns_response, id, addr, sender);
+                if guard_cache.prefetch(&cache_key) {
+                    CacheReturn::Prefetch(result, (cache, cache_key))
+                } else {
+                    CacheReturn::Found(result)
+                }
             } else {
                 debug!("Question is not found in cache");
-                CacheReturn::NotFound(Some((cache, question.clone())))
+                CacheReturn::NotFound(Some((cache, cache_key)))
             }
         } else {
             debug!("The amount of questions is not equal 1");
@@ -66,84 +135,200 @@ async fn get_response_from_cache<'a>(
     }
 }
 
+/// Get the time that the response `dns_response` is cached, where `duration` is `None` if the
+/// response should not be cached.
+///
+/// A response with a TTL of 0 is only cached if the minimum TTL raises it.
+fn get_cache_duration(
+    ttl_limits: &TtlLimits,
+    dns_response: &Dns,
+    duration: Option<Duration>,
+) -> Option<Duration> {
+    let duration = duration.or_else(|| get_ttl(dns_response))?;
+    let duration = ttl_limits.limit(dns_response, duration);
+    if duration > Duration::from_secs(0) {
+        Some(duration)
+    } else {
+        None
+    }
+}
+
+/// Wait for the response of the server and add it to the cache.
+///
+/// The time that the response is cached and optionally its TTLs are limited by `ttl_limits`.
+/// Returns the encoded response with the ID `id`.
 async fn get_response(
-    context: &Context,
-    cache_question: &Option<(&Mutex<Cache<Question, Dns>>, Question)>,
-    response: (
-        impl Future<Output = DohResult<(Dns, Option<Duration>)>>,
-        u32,
-    ),
+    upstream: &Mutex<Upstream>,
+    cache_key: &Option<(&ShardedCache<CacheKey, Dns>, CacheKey)>,
+    ttl_limits: &TtlLimits,
+    response: (ResponseFuture, u32),
+    timeout: Duration,
     id: u16,
-    addr: &SocketAddr,
-) -> Option<DohResult<()>> {
+) -> DohResult<Bytes> {
     let (response_future, connection_id) = response;
-    let timeout = context.timeout;
-    match create_timeout(timeout, response_future).await {
-        Ok(Ok((mut dns_response, duration))) => {
-            let addr = *addr;
-            let sender = &context.sender;
-            let result = send_response(&mut dns_response, id, addr, sender);
-            if let Some(duration) = duration {
-                if let Some((cache, question)) = cache_question {
-                    let mut guard_cache = cache.lock().await;
+    let result = match create_timeout(timeout, response_future).await {
+        Ok(Ok((dns_response, duration))) => {
+            let mut dns_response = ttl_limits.rewrite(dns_response);
+            dns_response.id = id;
+            let bytes = dns_response.to_bytes()?;
+            if let Some(duration) = get_cache_duration(ttl_limits, &dns_response, duration) {
+                if let Some((cache, cache_key)) = cache_key {
+                    let mut guard_cache = cache.lock(cache_key);
                     debug!(
                         "Add records in cache: {}, {}, {:?}",
-                        question, dns_response, duration
+                        cache_key, dns_response, duration
+                    );
+                    let size = bytes.len();
+                    guard_cache.put(cache_key.clone(), dns_response, size, duration);
+                    drop(guard_cache);
+                    debug!(
+                        "Cache usage: {} entries, {} bytes",
+                        cache.len(),
+                        cache.bytes()
                     );
-                    guard_cache.put(question.clone(), dns_response, duration);
                 }
             }
-            return Some(result);
+            return Ok(bytes);
         }
         Ok(Err(e)) => {
             error!("Could not retrieve DNS response from server: {}", e);
+            Err(e)
         }
         Err(e) => {
             error!("Timeout: {}", e);
+            Err(DohError::Timeout)
         }
+    };
+    let mut guard_upstream = upstream.lock().await;
+    guard_upstream.disconnect(connection_id);
+    result
+}
+
+/// Get the time, which the attempt `retry` may use of the `remaining` time.
+///
+/// A part of the remaining time is kept, so that a retry is possible if the server does not
+/// respond. The last attempt uses all of it.
+fn get_attempt_timeout(remaining: Duration, retry: u32) -> Duration {
+    if retry == MAX_RETRIES {
+        remaining
+    } else {
+        remaining.mul_f64(ATTEMPT_SHARE)
     }
-    let mut guard_remote_session = context.remote_session.lock().await;
-    guard_remote_session.disconnect(connection_id);
-    None
 }
 
 async fn get_response_from_remote(
     context: &Context,
-    cache_question: &Option<(&Mutex<Cache<Question, Dns>>, Question)>,
-    dns_request: &mut Dns,
-    addr: &SocketAddr,
-) -> Option<DohResult<()>> {
-    let mut guard_remote_session = context.remote_session.lock().await;
-    let result = guard_remote_session.start_request(dns_request).await;
-    drop(guard_remote_session);
-    match result {
-        Ok(response) => {
-            let id = dns_request.id;
-            get_response(context, cache_question, response, id, addr).await
+    upstream: usize,
+    cache_key: &Option<(&ShardedCache<CacheKey, Dns>, CacheKey)>,
+    dns_request: &Dns,
+    msg: &Bytes,
+) -> DohResult<Bytes> {
+    let upstream = &context.upstreams[upstream];
+    let deadline = Instant::now() + context.timeout;
+    let mut error = DohError::Timeout;
+    for retry in 0..=MAX_RETRIES {
+        let now = Instant::now();
+        if deadline <= now {
+            break;
         }
-        Err(e) => {
-            info!("Could not contact DNS server: {}", e);
-            None
+        let timeout = get_attempt_timeout(deadline - now, retry);
+
+        // The upstream is not locked while the hostname is resolved, so that other requests are
+        // not blocked.
+        let resolve = upstream.lock().await.resolve();
+        resolve.await;
+        let mut guard_upstream = upstream.lock().await;
+        if retry == 0 {
+            debug!("Forward DNS request to {}", *guard_upstream);
+        } else {
+            info!("Retry DNS request to {}: {}", *guard_upstream, retry);
         }
+        let result = guard_upstream.forward_request(dns_request, msg).await;
+        drop(guard_upstream);
+        error = match result {
+            Ok(response) => {
+                let id = dns_request.id;
+                let ttl_limits = &context.ttl_limits;
+                match get_response(upstream, cache_key, ttl_limits, response, timeout, id).await {
+                    Ok(bytes) => return Ok(bytes),
+                    Err(e) => e,
+                }
+            }
+            Err(DohError::CircuitOpen) => {
+                info!("Could not contact DNS server: {}", DohError::CircuitOpen);
+                return Err(DohError::CircuitOpen);
+            }
+            Err(e) => {
+                info!("Could not contact DNS server: {}", e);
+                e
+            }
+        };
     }
+    Err(error)
 }
 
-async fn get_response_from_cache_fallback<'a>(
-    context: &'a Context,
-    cache_question: Option<(&Mutex<Cache<Question, Dns>>, Question)>,
+/// Get the response from the server, where identical requests, which are in flight at the same
+/// time, are sent only once.
+///
+/// The later requests wait for the response of the first request, which is answered with their
+/// own ID.
+async fn get_response_from_remote_coalesced(
+    context: &Context,
+    upstream: usize,
+    cache_key: &Option<(&ShardedCache<CacheKey, Dns>, CacheKey)>,
     dns_request: &Dns,
-    addr: SocketAddr,
-) -> Option<DohResult<()>> {
-    if context.cache_fallback {
-        if let Some((cache, question)) = &cache_question {
-            let mut guard_cache = cache.lock().await;
-            if let Some(dns_response) = guard_cache.get_expired_fallback(question) {
-                let id = dns_request.id;
-                let sender = &context.sender;
-                debug!("Question is found in cache fallback");
-                Some(send_response(dns_response, id, addr, sender))
+    msg: &Bytes,
+) -> DohResult<Bytes> {
+    let key = match cache_key {
+        Some((_, key)) => key,
+        None => {
+            return get_response_from_remote(context, upstream, cache_key, dns_request, msg).await
+        }
+    };
+    match context.in_flight.join(key) {
+        Join::Leader(leader) => {
+            let result =
+                get_response_from_remote(context, upstream, cache_key, dns_request, msg).await;
+            leader.finish(result)
+        }
+        Join::Waiter(receiver) => {
+            debug!("Wait for the identical request in flight: {}", key);
+            match receiver.await {
+                Ok(Ok(bytes)) => Ok(set_id(bytes, dns_request.id)),
+                Ok(Err(e)) => Err(DohError::InFlight(e)),
+                // The first request was cancelled.
+                Err(_) => Err(DohError::Timeout),
+            }
+        }
+    }
+}
+
+/// Get a stale response from the cache (see RFC 8767).
+///
+/// If the client supports EDNS, then the response contains an Extended DNS Error, which marks the
+/// response as stale.
+async fn get_stale_response_from_cache(
+    context: &Context,
+    cache_key: &Option<(&ShardedCache<CacheKey, Dns>, CacheKey)>,
+    dns_request: &Dns,
+) -> Option<DohResult<Bytes>> {
+    if let Some(serve_stale) = &context.serve_stale {
+        if let Some((cache, cache_key)) = cache_key {
+            let mut guard_cache = cache.lock(cache_key);
+            if let Some(dns_response) = guard_cache.get_stale(cache_key, serve_stale.max_age) {
+                debug!("Question is found in stale cache");
+                let stale_response = create_stale_response(dns_response, dns_request.id);
+                let result = stale_response.to_bytes().map(|bytes| {
+                    if has_opt(dns_request) {
+                        let text = "The server did not respond in time";
+                        add_extended_error(bytes, ExtendedError::StaleAnswer, text)
+                    } else {
+                        bytes
+                    }
+                });
+                Some(result.map_err(DohError::from))
             } else {
-                debug!("Question is not found in cache fallback");
+                debug!("Question is not found in stale cache");
                 None
             }
         } else {
@@ -151,7 +336,7 @@ async fn get_response_from_cache_fallback<'a>(
             None
         }
     } else {
-        debug!("Cache fallback is disable");
+        debug!("Serve stale is disable");
         None
     }
 }
@@ -161,27 +346,232 @@ pub async fn request_handler(
     addr: SocketAddr,
     context: &'static Context,
 ) -> DohResult<()> {
-    let mut dns_request = Dns::decode(&msg)?;
+    let dns_request = match Dns::decode(&msg) {
+        Ok(dns_request) => dns_request,
+        Err(e) => {
+            if let Some(bytes) = create_header_error_response(&msg) {
+                context.sender.unbounded_send((bytes, addr))?;
+            }
+            return Err(e.into());
+        }
+    };
     if dns_request.is_response() {
         return Err(DohError::DnsNotRequest(dns_request));
     }
+    if dns_request.flags.opcode != Opcode::Query {
+        debug!("Opcode is not supported: {:?}", dns_request.flags.opcode);
+        let extended_error = ExtendedError::NotSupported;
+        let text = "Only queries are supported";
+        let sender = &context.sender;
+        return send_error_response(
+            &dns_request,
+            RCode::NotImp,
+            extended_error,
+            text,
+            addr,
+            sender,
+        );
+    }
 
-    let cache = get_response_from_cache(context, &dns_request, &addr).await;
-    let cache_question = match cache {
+    let upstream = context.router.route(&dns_request);
+
+    let edns = get_edns(&msg);
+    let cache = get_response_from_cache(context, upstream, &dns_request, edns, &addr).await;
+    let cache_key = match cache {
         CacheReturn::Found(result) => return result,
-        CacheReturn::NotFound(cache_question) => cache_question,
+        CacheReturn::Prefetch(result, cache_key) => {
+            debug!("Prefetch {}", cache_key.1);
+            let cache_key = Some(cache_key);
+            let prefetch =
+                get_response_from_remote(context, upstream, &cache_key, &dns_request, &msg).await;
+            if let Err(e) = prefetch {
+                error!("Could not prefetch: {}", e);
+            }
+            // If the response was cached, then the entry is new and this does nothing.
+            if let Some((cache, cache_key)) = &cache_key {
+                cache.lock(cache_key).reset_prefetch(cache_key);
+            }
+            return result;
+        }
+        CacheReturn::NotFound(cache_key) => cache_key,
     };
 
-    let remote = get_response_from_remote(context, &cache_question, &mut dns_request, &addr).await;
-    if let Some(result) = remote {
-        return result;
-    }
+    let stale = match get_stale_response_from_cache(context, &cache_key, &dns_request).await {
+        Some(Ok(bytes)) => Some(bytes),
+        Some(Err(e)) => {
+            error!("Could not create stale response: {}", e);
+            None
+        }
+        None => None,
+    };
 
-    let fallback =
-        get_response_from_cache_fallback(context, cache_question, &dns_request, addr).await;
-    if let Some(result) = fallback {
-        return result;
+    let remote = async move {
+        let result =
+            get_response_from_remote_coalesced(context, upstream, &cache_key, &dns_request, &msg)
+                .await;
+        (result, dns_request)
+    };
+    let (result, dns_request) = match (&context.serve_stale, &stale) {
+        (Some(serve_stale), Some(stale_response)) => {
+            // The client-response timer (see RFC 8767 section 5).
+            let remote = Box::pin(remote);
+            match select(remote, delay_for(serve_stale.timeout)).await {
+                Either::Left((result, _)) => result,
+                Either::Right((_, remote)) => {
+                    debug!("Answer with stale response, refresh the cache in the background");
+                    spawn(async move {
+                        if let (Err(e), _) = remote.await {
+                            error!("Could not refresh the cache: {}", e);
+                        }
+                    });
+                    context
+                        .sender
+                        .unbounded_send((stale_response.clone(), addr))?;
+                    return Ok(());
+                }
+            }
+        }
+        _ => remote.await,
+    };
+
+    let error = match result {
+        Ok(bytes) => {
+            context.sender.unbounded_send((bytes, addr))?;
+            return Ok(());
+        }
+        Err(e) => e,
+    };
+
+    if let Some(stale_response) = stale {
+        debug!("Answer with stale response");
+        context.sender.unbounded_send((stale_response, addr))?;
+        return Ok(());
     }
 
+    let extended_error = ExtendedError::from(&error);
+    // Only the kind of the error is sent, because the message can contain internal details, for
+    // example the whole DNS response of a mismatch.
+    let text = error.kind();
+    let sender = &context.sender;
+    send_error_response(
+        &dns_request,
+        RCode::ServFail,
+        extended_error,
+        text,
+        addr,
+        sender,
+    )?;
     Err(DohError::CouldNotGetResponse(dns_request))
 }
+
+/// Check the connection to the DoH server every `interval` and reconnect in the background if it
+/// was closed.
+///
+/// A failed reconnect is only logged as an error when the state changes, because it is tried again
+/// every interval while the circuit breaker is open.
+pub async fn reconnect_handler(context: &'static Context, interval: Duration) {
+    let mut failed = false;
+    loop {
+        delay_for(interval).await;
+        let resolve = context.upstreams[0].lock().await.resolve();
+        resolve.await;
+        let mut guard_upstream = context.upstreams[0].lock().await;
+        match guard_upstream.reconnect().await {
+            Ok(()) if failed => {
+                info!("Reconnected to {}", *guard_upstream);
+                failed = false;
+            }
+            Ok(()) => {}
+            Err(e) if failed => debug!("Could not reconnect to {}: {}", *guard_upstream, e),
+            Err(e) => {
+                error!("Could not reconnect to {}: {}", *guard_upstream, e);
+                failed = true;
+            }
+        }
+    }
+}
+
+/// Save the cache to the cache file, if the cache is persistent.
+pub async fn save_cache(context: &Context) {
+    if let (Some(persist), Some(cache)) = (&context.persist, &context.cache) {
+        let (bytes, n) = encode(cache);
+        match write_file(&persist.path, &bytes) {
+            Ok(()) => debug!("Saved {} cache entries to {}", n, persist.path),
+            Err(e) => error!("Could not save the cache to {}: {}", persist.path, e),
+        }
+    }
+}
+
+pub async fn persist_handler(context: &'static Context, interval: Duration) {
+    loop {
+        delay_for(interval).await;
+        save_cache(context).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dns_message_parser::{Class, RCode, RData, RR};
+
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use super::{
+        create_header_error_response, get_attempt_timeout, get_cache_duration, MAX_RETRIES,
+    };
+    use crate::dns::{create_response, create_test_request};
+    use crate::ttl::TtlLimits;
+
+    #[test]
+    fn test_create_header_error_response() {
+        let query = [0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, 0xff];
+        let response = create_header_error_response(&query).unwrap();
+        assert_eq!(
+            &response[..],
+            &[0x12, 0x34, 0x81, 0x81, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        // An unknown opcode.
+        let query = [0x12, 0x34, 0x19, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+        let response = create_header_error_response(&query).unwrap();
+        assert_eq!(&response[..4], &[0x12, 0x34, 0x99, 0x84]);
+
+        let response = [0x12, 0x34, 0x81, 0x80, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(create_header_error_response(&response).is_none());
+        assert!(create_header_error_response(&query[..4]).is_none());
+    }
+
+    #[test]
+    fn test_get_attempt_timeout() {
+        let timeout = Duration::from_secs(2);
+        let first = get_attempt_timeout(timeout, 0);
+        assert!(first > timeout / 2);
+        assert!(first < timeout);
+        let remaining = Duration::from_millis(100);
+        assert_eq!(get_attempt_timeout(remaining, MAX_RETRIES), remaining);
+    }
+
+    #[test]
+    fn test_get_cache_duration() {
+        let dns_request = create_test_request("example.com");
+        let mut dns_response = create_response(&dns_request, RCode::NoError);
+        let domain_name = dns_request.questions[0].get_domain_name().clone();
+        let rdata = RData::A(Ipv4Addr::new(192, 0, 2, 1));
+        dns_response
+            .answers
+            .push(RR::new(domain_name, Class::IN, 0, rdata));
+
+        let negative_max = Duration::from_secs(900);
+        let ttl_limits = TtlLimits::new(Duration::from_secs(0), None, negative_max, false);
+        assert_eq!(get_cache_duration(&ttl_limits, &dns_response, None), None);
+        let min = Duration::from_secs(60);
+        let ttl_limits = TtlLimits::new(min, None, negative_max, false);
+        assert_eq!(
+            get_cache_duration(&ttl_limits, &dns_response, None),
+            Some(min)
+        );
+
+        let dns_response = create_response(&dns_request, RCode::Refused);
+        assert_eq!(get_cache_duration(&ttl_limits, &dns_response, None), None);
+    }
+}
@@ -0,0 +1,188 @@
+use bytes::{Bytes, BytesMut};
+
+use crate::{CacheKey, DohError, DohResult};
+
+use futures::channel::oneshot::{channel, Receiver, Sender};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// The result of a request, which is shared with all identical requests.
+pub(crate) type SharedResult = Result<Bytes, Arc<DohError>>;
+
+/// The requests to the upstreams, which are in flight.
+///
+/// Identical requests, which arrive while the first request is in flight, are not sent to the
+/// upstream again, but wait for the response of the first request.
+pub(crate) struct InFlight {
+    requests: Mutex<HashMap<CacheKey, Vec<Sender<SharedResult>>>>,
+}
+
+/// The role of a request, after it joined the requests in flight.
+pub(crate) enum Join<'a> {
+    /// The request is the first one and has to be sent to the upstream.
+    Leader(Leader<'a>),
+    /// An identical request is in flight, the receiver gets its result.
+    Waiter(Receiver<SharedResult>),
+}
+
+/// The first request of identical requests.
+///
+/// If it is dropped without calling `finish`, then the waiting requests are cancelled.
+pub(crate) struct Leader<'a> {
+    in_flight: &'a InFlight,
+    cache_key: &'a CacheKey,
+    finished: bool,
+}
+
+impl InFlight {
+    pub(crate) fn new() -> InFlight {
+        InFlight {
+            requests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<CacheKey, Vec<Sender<SharedResult>>>> {
+        self.requests.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn join<'a>(&'a self, cache_key: &'a CacheKey) -> Join<'a> {
+        let mut guard_requests = self.lock();
+        match guard_requests.get_mut(cache_key) {
+            Some(waiters) => {
+                let (sender, receiver) = channel();
+                waiters.push(sender);
+                Join::Waiter(receiver)
+            }
+            None => {
+                guard_requests.insert(cache_key.clone(), Vec::new());
+                Join::Leader(Leader {
+                    in_flight: self,
+                    cache_key,
+                    finished: false,
+                })
+            }
+        }
+    }
+}
+
+impl<'a> Leader<'a> {
+    fn remove(&self) -> Vec<Sender<SharedResult>> {
+        let mut guard_requests = self.in_flight.lock();
+        guard_requests.remove(self.cache_key).unwrap_or_default()
+    }
+
+    /// Send the result to all waiting requests.
+    ///
+    /// If the request failed and other requests wait for it, then the error is shared and
+    /// returned as `DohError::InFlight`.
+    pub(crate) fn finish(mut self, result: DohResult<Bytes>) -> DohResult<Bytes> {
+        let waiters = self.remove();
+        // The entry could belong to the next leader, after it was removed.
+        self.finished = true;
+        if waiters.is_empty() {
+            return result;
+        }
+        debug!("Answer {} identical requests", waiters.len());
+        let (shared_result, result) = match result {
+            Ok(bytes) => (Ok(bytes.clone()), Ok(bytes)),
+            Err(e) => {
+                let e = Arc::new(e);
+                (Err(e.clone()), Err(DohError::InFlight(e)))
+            }
+        };
+        for waiter in waiters {
+            let _ = waiter.send(shared_result.clone());
+        }
+        result
+    }
+}
+
+impl<'a> Drop for Leader<'a> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.remove();
+        }
+    }
+}
+
+/// Replace the ID of the encoded DNS message `msg`.
+pub(crate) fn set_id(msg: Bytes, id: u16) -> Bytes {
+    if msg.len() < 2 {
+        return msg;
+    }
+    let mut bytes = BytesMut::from(&msg[..]);
+    bytes[..2].copy_from_slice(&id.to_be_bytes());
+    bytes.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use futures::executor::block_on;
+
+    use super::{set_id, InFlight, Join};
+    use crate::dns::create_test_request;
+    use crate::{CacheKey, DohError};
+
+    fn create_cache_key() -> CacheKey {
+        let dns_request = create_test_request("example.com");
+        let question = dns_request.questions[0].clone();
+        CacheKey::new(0, question, &dns_request.flags, None)
+    }
+
+    #[test]
+    fn test_in_flight() {
+        let in_flight = InFlight::new();
+        let cache_key = create_cache_key();
+        let leader = match in_flight.join(&cache_key) {
+            Join::Leader(leader) => leader,
+            Join::Waiter(_) => panic!("The first request has to be the leader"),
+        };
+        let receivers: Vec<_> = (0..2)
+            .map(|_| match in_flight.join(&cache_key) {
+                Join::Leader(_) => panic!("An identical request is in flight"),
+                Join::Waiter(receiver) => receiver,
+            })
+            .collect();
+
+        let bytes = Bytes::from_static(&[0x12, 0x34, 0x81, 0x80]);
+        assert_eq!(leader.finish(Ok(bytes.clone())).unwrap(), bytes);
+        for receiver in receivers {
+            assert_eq!(block_on(receiver).unwrap().unwrap(), bytes);
+        }
+
+        let leader = match in_flight.join(&cache_key) {
+            Join::Leader(leader) => leader,
+            Join::Waiter(_) => panic!("The first request is finished"),
+        };
+        let receiver = match in_flight.join(&cache_key) {
+            Join::Leader(_) => panic!("An identical request is in flight"),
+            Join::Waiter(receiver) => receiver,
+        };
+        let result = leader.finish(Err(DohError::Timeout));
+        assert!(matches!(result, Err(DohError::InFlight(_))));
+        assert!(block_on(receiver).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_in_flight_drop() {
+        let in_flight = InFlight::new();
+        let cache_key = create_cache_key();
+        let leader = in_flight.join(&cache_key);
+        let receiver = match in_flight.join(&cache_key) {
+            Join::Leader(_) => panic!("An identical request is in flight"),
+            Join::Waiter(receiver) => receiver,
+        };
+        drop(leader);
+        assert!(block_on(receiver).is_err());
+        assert!(matches!(in_flight.join(&cache_key), Join::Leader(_)));
+    }
+
+    #[test]
+    fn test_set_id() {
+        let msg = Bytes::from_static(&[0x12, 0x34, 0x81, 0x80]);
+        assert_eq!(&set_id(msg, 0xabcd)[..], &[0xab, 0xcd, 0x81, 0x80]);
+    }
+}
@@ -1,28 +1,74 @@
 #[macro_use]
 extern crate log;
 
+use tokio::net::udp::RecvHalf;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::spawn;
 
 mod cmd;
-pub use cmd::{get_app, get_listen_config, get_remote_host};
+pub use cmd::{
+    get_app, get_backup_pins, get_bench_names, get_bootstrap, get_forward_rules, get_listen_config,
+    get_pins, get_qtype, get_question, get_remote_host,
+};
 
 mod cache;
 use cache::Cache;
 
+mod sharded_cache;
+use sharded_cache::ShardedCache;
+
+mod cache_key;
+use cache_key::CacheKey;
+
+mod ca;
+
+mod der;
+
+mod client_cert;
+use client_cert::reload_handler;
+
 mod config;
-pub use config::Config;
+pub use config::{CacheOptions, Config, RemoteOptions, StaleOptions, TlsOptions, TtlOptions};
+
+mod control;
+use control::control_handler;
+pub use control::ctl;
 
 mod context;
 use context::Context;
 
+mod dns;
+
+mod edns;
+
 mod error;
 use error::{Error as DohError, Result as DohResult};
 
+mod in_flight;
+
+mod logger;
+pub use logger::init_logger;
+
+mod forward;
+pub use forward::Rule as ForwardRule;
+
+mod persist;
+
+mod stale;
+
+mod ttl;
+
+mod query;
+pub use query::query;
+
+mod bench;
+pub use bench::{bench, Names as BenchNames};
+
 mod remote;
-pub use remote::Host as RemoteHost;
+pub use remote::{Bootstrap, Host as RemoteHost, Pin};
 
 mod handler;
-use handler::request_handler;
+use handler::{persist_handler, reconnect_handler, request_handler, save_cache};
 
 mod listen;
 pub use listen::Config as ListenConfig;
@@ -33,10 +79,53 @@ use dns_message_parser::MAXIMUM_DNS_PACKET_SIZE;
 
 /// Run the `doh-client` with a specific configuration.
 pub async fn run(config: Config) -> DohResult<()> {
-    let (mut recv, context) = config.into().await?;
+    let (recv, control_listener, context) = config.into().await?;
 
     let context: &'static Context = Box::leak(Box::new(context));
 
+    if let Some(keepalive) = context.keepalive {
+        spawn(reconnect_handler(context, keepalive));
+    }
+
+    if let Some(interval) = context
+        .persist
+        .as_ref()
+        .and_then(|persist| persist.interval)
+    {
+        spawn(persist_handler(context, interval));
+    }
+
+    if let Some(client_cert) = &context.client_cert {
+        spawn(reload_handler(client_cert.clone()));
+    }
+
+    if let Some(control_listener) = control_listener {
+        spawn(control_handler(control_listener, context));
+    }
+
+    tokio::select! {
+        result = receive_handler(recv, context) => result,
+        result = shutdown_signal() => {
+            result?;
+            info!("Shutdown");
+            save_cache(context).await;
+            Ok(())
+        }
+    }
+}
+
+/// Wait for SIGINT or SIGTERM.
+async fn shutdown_signal() -> DohResult<()> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = interrupt.recv() => {},
+        _ = terminate.recv() => {},
+    }
+    Ok(())
+}
+
+async fn receive_handler(mut recv: RecvHalf, context: &'static Context) -> DohResult<()> {
     let mut buffer: [u8; MAXIMUM_DNS_PACKET_SIZE] = [0; MAXIMUM_DNS_PACKET_SIZE];
     loop {
         let (n, addr) = recv.recv_from(&mut buffer[..]).await?;
@@ -0,0 +1,116 @@
+use env_logger::{Builder, Logger as EnvLogger};
+
+use log::{set_boxed_logger, set_max_level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The prefix of the log targets of this crate.
+const TARGET: &str = "doh_client";
+
+/// The value of `LEVEL`, if the level is not overridden.
+const NO_LEVEL: usize = usize::MAX;
+
+/// The log level of this crate, which overrides the `RUST_LOG` environment variable.
+static LEVEL: AtomicUsize = AtomicUsize::new(NO_LEVEL);
+
+/// The maximum log level of the `RUST_LOG` environment variable.
+static DEFAULT_MAX_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Error as usize);
+
+fn to_level_filter(level: usize) -> Option<LevelFilter> {
+    match level {
+        0 => Some(LevelFilter::Off),
+        1 => Some(LevelFilter::Error),
+        2 => Some(LevelFilter::Warn),
+        3 => Some(LevelFilter::Info),
+        4 => Some(LevelFilter::Debug),
+        5 => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+/// A logger, which filters the records by the `RUST_LOG` environment variable (see `env_logger`),
+/// unless the level of this crate is overridden at runtime by `set_log_level`.
+struct Logger {
+    /// Filters the records by the `RUST_LOG` environment variable.
+    filter: EnvLogger,
+    /// Writes all records, which passed the filter.
+    writer: EnvLogger,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match to_level_filter(LEVEL.load(Ordering::Relaxed)) {
+            Some(level) if metadata.target().starts_with(TARGET) => metadata.level() <= level,
+            _ => self.filter.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.writer.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.writer.flush();
+    }
+}
+
+/// Initialize the logger, which is configured by the `RUST_LOG` environment variable (see
+/// `env_logger`). The log level can be changed at runtime via the control socket.
+pub fn init_logger() -> Result<(), SetLoggerError> {
+    let filter = Builder::from_default_env().build();
+    let writer = Builder::new()
+        .filter_level(LevelFilter::Trace)
+        .format_timestamp(None)
+        .build();
+    let default_max_level = filter.filter();
+    set_boxed_logger(Box::new(Logger { filter, writer }))?;
+    DEFAULT_MAX_LEVEL.store(default_max_level as usize, Ordering::Relaxed);
+    set_max_level(default_max_level);
+    Ok(())
+}
+
+/// Override the log level of this crate or use the level of the `RUST_LOG` environment variable
+/// again, if `level` is `None`.
+pub(crate) fn set_log_level(level: Option<LevelFilter>) {
+    let default_max_level =
+        to_level_filter(DEFAULT_MAX_LEVEL.load(Ordering::Relaxed)).unwrap_or(LevelFilter::Error);
+    match level {
+        Some(level) => {
+            LEVEL.store(level as usize, Ordering::Relaxed);
+            set_max_level(level.max(default_max_level));
+        }
+        None => {
+            LEVEL.store(NO_LEVEL, Ordering::Relaxed);
+            set_max_level(default_max_level);
+        }
+    }
+}
+
+/// Get the log level of this crate, if it is overridden.
+pub(crate) fn get_log_level() -> Option<LevelFilter> {
+    to_level_filter(LEVEL.load(Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use log::LevelFilter;
+
+    use super::to_level_filter;
+
+    #[test]
+    fn test_to_level_filter() {
+        for level in &[
+            LevelFilter::Off,
+            LevelFilter::Error,
+            LevelFilter::Warn,
+            LevelFilter::Info,
+            LevelFilter::Debug,
+            LevelFilter::Trace,
+        ] {
+            assert_eq!(to_level_filter(*level as usize), Some(*level));
+        }
+        assert_eq!(to_level_filter(usize::MAX), None);
+    }
+}
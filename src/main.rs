@@ -3,16 +3,32 @@ extern crate log;
 #[macro_use]
 extern crate clap;
 
-use env_logger::Builder;
+use doh_client::{
+    bench, ctl, get_app, get_backup_pins, get_bench_names, get_bootstrap, get_forward_rules,
+    get_listen_config, get_pins, get_qtype, get_question, get_remote_host, init_logger, query, run,
+    CacheOptions, Config, RemoteOptions, StaleOptions, TlsOptions, TtlOptions,
+};
 
-use doh_client::{get_app, get_listen_config, get_remote_host, run, Config};
+use std::process::exit;
 
 #[tokio::main]
 async fn main() {
     let matches = get_app().get_matches();
 
-    let mut builder = Builder::from_default_env();
-    builder.format_timestamp(None).init();
+    init_logger().expect("Could not initialize the logger");
+
+    if let Some(matches) = matches.subcommand_matches("ctl") {
+        let socket = matches.value_of("socket").unwrap();
+        let command: Vec<&str> = matches.values_of("command").unwrap().collect();
+        match ctl(socket, &command).await {
+            Ok(output) => print!("{}", output),
+            Err(e) => {
+                error!("Could not execute control command: {}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
 
     let listen_config = match get_listen_config(&matches) {
         Ok(listen_config) => listen_config,
@@ -28,26 +44,152 @@ async fn main() {
             return;
         }
     };
-    let domain = matches.value_of("domain").unwrap();
-    let cafile = matches.value_of("cafile").unwrap();
-    let path = matches.value_of("path").unwrap();
-    let retries: u32 = value_t!(matches, "retries", u32).unwrap_or(3);
-    let timeout: u64 = value_t!(matches, "timeout", u64).unwrap_or(2);
-    let post: bool = !matches.is_present("get");
-    let cache_size: usize = value_t!(matches, "cache-size", usize).unwrap_or(1024);
-    let cache_fallback: bool = matches.is_present("cache-fallback");
+    let bootstrap = match get_bootstrap(&matches) {
+        Ok(bootstrap) => bootstrap,
+        Err(e) => {
+            error!("Could not get bootstrap resolver: {:?}", e);
+            return;
+        }
+    };
+    let forward_rules = match get_forward_rules(&matches) {
+        Ok(forward_rules) => forward_rules,
+        Err(e) => {
+            error!("Could not get forward rules: {:?}", e);
+            return;
+        }
+    };
+    let pins = match get_pins(&matches) {
+        Ok(pins) => pins,
+        Err(e) => {
+            error!("Could not get pins: {:?}", e);
+            return;
+        }
+    };
+    let backup_pins = match get_backup_pins(&matches) {
+        Ok(backup_pins) => backup_pins,
+        Err(e) => {
+            error!("Could not get backup pins: {:?}", e);
+            return;
+        }
+    };
+    let remote = RemoteOptions {
+        host: remote_host,
+        domain: matches.value_of("domain").unwrap().to_string(),
+        path: matches.value_of("path").unwrap().to_string(),
+        retries: value_t!(matches, "retries", u32).unwrap_or(3),
+        timeout: value_t!(matches, "timeout", u64).unwrap_or(2),
+        connect_timeout: value_t!(matches, "connect-timeout", u64).unwrap_or(2),
+        handshake_timeout: value_t!(matches, "handshake-timeout", u64).unwrap_or(2),
+        keepalive: value_t!(matches, "keepalive", u64).unwrap_or(30),
+        post: !matches.is_present("get"),
+        bootstrap,
+    };
+    let mut cafiles: Vec<String> = values_t!(matches, "cafiles", String).unwrap_or_default();
+    if let Some(cafile) = matches.value_of("cafile") {
+        cafiles.insert(0, cafile.to_string());
+    }
+    let client_cert = match (
+        matches.value_of("client-cert"),
+        matches.value_of("client-key"),
+    ) {
+        (Some(cert_file), Some(key_file)) => Some((cert_file.to_string(), key_file.to_string())),
+        _ => None,
+    };
+    let tls = TlsOptions {
+        cafiles,
+        cadirs: values_t!(matches, "cadirs", String).unwrap_or_default(),
+        pins,
+        backup_pins,
+        client_cert,
+    };
+    let fallback = if matches.is_present("cache-fallback") {
+        Some(StaleOptions {
+            max_age: value_t!(matches, "stale-max-age", u64).unwrap_or(86400),
+            timeout: value_t!(matches, "stale-timeout", u64).unwrap_or(1800),
+        })
+    } else {
+        None
+    };
+    let cache = CacheOptions {
+        size: value_t!(matches, "cache-size", usize).unwrap_or(1024),
+        max_bytes: value_t!(matches, "cache-max-bytes", usize).unwrap_or(0),
+        prefetch: value_t!(matches, "prefetch", u32).unwrap_or(0),
+        file: matches.value_of("cache-file").map(|file| file.to_string()),
+        save_interval: value_t!(matches, "cache-save-interval", u64).unwrap_or(300),
+        fallback,
+    };
+    let ttl = TtlOptions {
+        min: value_t!(matches, "min-ttl", u64).unwrap_or(0),
+        max: value_t!(matches, "max-ttl", u64).unwrap_or(0),
+        rewrite: matches.is_present("rewrite-ttl"),
+        negative_max: value_t!(matches, "negative-ttl-max", u64).unwrap_or(3600),
+    };
+    let control_socket = matches
+        .value_of("control-socket")
+        .map(|control_socket| control_socket.to_string());
     let result = Config::new(
         listen_config,
-        remote_host,
-        domain,
-        cafile,
-        path,
-        retries,
-        timeout,
-        post,
-        cache_size,
-        cache_fallback,
+        control_socket,
+        remote,
+        tls,
+        cache,
+        ttl,
+        forward_rules,
     );
+    if let Some(matches) = matches.subcommand_matches("query") {
+        let question = match get_question(matches) {
+            Ok(question) => question,
+            Err(e) => {
+                error!("Could not get question: {:?}", e);
+                exit(1);
+            }
+        };
+        let json = matches.is_present("json");
+        let result = match result {
+            Ok(config) => query(config, question, json).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(output) => print!("{}", output),
+            Err(e) => {
+                error!("Could not query: {}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("bench") {
+        let names = match get_bench_names(matches) {
+            Ok(names) => names,
+            Err(e) => {
+                error!("Could not get domain names: {:?}", e);
+                exit(1);
+            }
+        };
+        let qtype = match get_qtype(matches) {
+            Ok(qtype) => qtype,
+            Err(e) => {
+                error!("Could not get query type: {:?}", e);
+                exit(1);
+            }
+        };
+        let requests: usize = value_t!(matches, "requests", usize).unwrap_or(1000);
+        let concurrency: usize = value_t!(matches, "concurrency", usize).unwrap_or(10);
+        let result = match result {
+            Ok(config) => bench(config, names, qtype, requests, concurrency).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(output) => print!("{}", output),
+            Err(e) => {
+                error!("Could not run benchmark: {}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+
     match result {
         Ok(config) => {
             if let Err(e) = run(config).await {
@@ -0,0 +1,367 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::dns::create_request;
+use crate::edns::Edns;
+use crate::forward::Router;
+use crate::{CacheKey, DohResult, ShardedCache};
+
+use dns_message_parser::Dns;
+
+use ring::digest::{digest, SHA256, SHA256_OUTPUT_LEN};
+
+use std::convert::TryFrom;
+use std::fs::{read, rename, write};
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The magic number at the start of a cache file.
+const MAGIC: &[u8; 4] = b"DOHC";
+
+/// The version of the cache file format, which has to be increased on every incompatible change.
+const VERSION: u8 = 1;
+
+/// The length of the magic number, the version and the SHA-256 digest of the entries.
+const HEADER_LENGTH: usize = MAGIC.len() + 1 + SHA256_OUTPUT_LEN;
+
+/// The flags of the EDNS data of a cache key.
+const EDNS_PRESENT: u8 = 0x01;
+const EDNS_DNSSEC_OK: u8 = 0x02;
+const EDNS_CLIENT_SUBNET: u8 = 0x04;
+
+/// The configuration of the persistent cache.
+pub(crate) struct Persist {
+    /// The path of the cache file.
+    pub(crate) path: String,
+    /// The time between two saves of the cache, if it is set.
+    pub(crate) interval: Option<Duration>,
+}
+
+impl Persist {
+    pub(crate) fn new(path: String, interval: Option<Duration>) -> Persist {
+        Persist { path, interval }
+    }
+}
+
+/// Create a query, which contains the question and the flags of the cache key.
+fn create_query(cache_key: &CacheKey) -> Dns {
+    let mut dns = create_request(cache_key.question.clone());
+    dns.flags.rd = cache_key.rd;
+    dns.flags.cd = cache_key.cd;
+    dns
+}
+
+fn put_slice(bytes: &mut BytesMut, slice: &[u8]) {
+    bytes.put_u16(slice.len() as u16);
+    bytes.put_slice(slice);
+}
+
+/// Encode an entry, where the expiry is converted to the milliseconds since the UNIX epoch.
+fn encode_entry(
+    bytes: &mut BytesMut,
+    cache_key: &CacheKey,
+    dns: &Dns,
+    expiry: SystemTime,
+    ttl: Duration,
+) -> DohResult<()> {
+    let query = create_query(cache_key).to_bytes()?;
+    let response = dns.to_bytes()?;
+    let expiry = expiry.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    bytes.put_u64(expiry.as_millis() as u64);
+    bytes.put_u64(ttl.as_millis() as u64);
+    put_slice(bytes, &query);
+    match &cache_key.edns {
+        Some(edns) => {
+            let mut flags = EDNS_PRESENT;
+            if edns.dnssec_ok {
+                flags |= EDNS_DNSSEC_OK;
+            }
+            match &edns.client_subnet {
+                Some(client_subnet) => {
+                    bytes.put_u8(flags | EDNS_CLIENT_SUBNET);
+                    put_slice(bytes, client_subnet);
+                }
+                None => bytes.put_u8(flags),
+            }
+        }
+        None => bytes.put_u8(0),
+    }
+    put_slice(bytes, &response);
+    Ok(())
+}
+
+/// Encode all entries of the cache, which are not expired.
+///
+/// Returns the encoded cache file and the number of entries.
+pub(crate) fn encode(cache: &ShardedCache<CacheKey, Dns>) -> (Bytes, usize) {
+    let now = Instant::now();
+    let system_now = SystemTime::now();
+    let mut entries = BytesMut::new();
+    let mut n = 0;
+    cache.for_each(|cache| {
+        for (cache_key, dns, expiry, ttl) in cache.iter() {
+            let expiry = system_now + expiry.saturating_duration_since(now);
+            let mut entry = BytesMut::new();
+            match encode_entry(&mut entry, cache_key, dns, expiry, ttl) {
+                Ok(()) => {
+                    entries.extend_from_slice(&entry);
+                    n += 1;
+                }
+                Err(e) => debug!("Could not encode cache entry {}: {}", cache_key, e),
+            }
+        }
+    });
+
+    let mut bytes = BytesMut::with_capacity(HEADER_LENGTH + entries.len());
+    bytes.put_slice(MAGIC);
+    bytes.put_u8(VERSION);
+    bytes.put_slice(digest(&SHA256, &entries).as_ref());
+    bytes.extend_from_slice(&entries);
+    (bytes.freeze(), n)
+}
+
+/// Write the encoded cache file atomically, so that a crash does not leave a partial file.
+pub(crate) fn write_file(path: &str, bytes: &[u8]) -> IoResult<()> {
+    let tmp_path = format!("{}.tmp", path);
+    write(&tmp_path, bytes)?;
+    rename(&tmp_path, path)
+}
+
+/// A reader of the entries of a cache file, which returns `None` if the data is truncated.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.data.len() < n {
+            return None;
+        }
+        let (bytes, data) = self.data.split_at(n);
+        self.data = data;
+        Some(bytes)
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        Some(self.read(1)?[0])
+    }
+
+    fn read_u64(&mut self) -> Option<u64> {
+        let bytes = self.read(8)?;
+        Some(u64::from_be_bytes(<[u8; 8]>::try_from(bytes).ok()?))
+    }
+
+    fn read_slice(&mut self) -> Option<&'a [u8]> {
+        let bytes = self.read(2)?;
+        let length = u16::from_be_bytes([bytes[0], bytes[1]]);
+        self.read(length as usize)
+    }
+}
+
+/// A decoded entry of a cache file.
+struct Entry {
+    cache_key: CacheKey,
+    dns: Dns,
+    size: usize,
+    expiry: SystemTime,
+    ttl: Duration,
+}
+
+fn decode_entry(reader: &mut Reader, router: &Router) -> Option<Entry> {
+    let expiry = UNIX_EPOCH + Duration::from_millis(reader.read_u64()?);
+    let ttl = Duration::from_millis(reader.read_u64()?);
+    let query = Dns::decode(&reader.read_slice()?).ok()?;
+    let flags = reader.read_u8()?;
+    let edns = if flags & EDNS_PRESENT == 0 {
+        None
+    } else {
+        let client_subnet = if flags & EDNS_CLIENT_SUBNET == 0 {
+            None
+        } else {
+            Some(reader.read_slice()?.to_vec())
+        };
+        Some(Edns {
+            dnssec_ok: flags & EDNS_DNSSEC_OK != 0,
+            client_subnet,
+        })
+    };
+    let response = reader.read_slice()?;
+    let dns = Dns::decode(&response).ok()?;
+
+    // The upstream is selected again, because the forward rules could have been changed.
+    let upstream = router.route(&query);
+    let question = query.questions.first()?.clone();
+    let cache_key = CacheKey::new(upstream, question, &query.flags, edns);
+    Some(Entry {
+        cache_key,
+        dns,
+        size: response.len(),
+        expiry,
+        ttl,
+    })
+}
+
+fn invalid_data(error: &str) -> IoError {
+    IoError::new(ErrorKind::InvalidData, error)
+}
+
+/// Load the entries of the cache file `path`, which are not expired, into the cache.
+///
+/// The file is only used if it is not corrupted and has the same version, otherwise an error is
+/// returned and the cache is not changed. Returns the number of loaded entries.
+pub(crate) fn load(
+    cache: &ShardedCache<CacheKey, Dns>,
+    router: &Router,
+    path: &str,
+) -> IoResult<usize> {
+    let data = read(path)?;
+    if data.len() < HEADER_LENGTH || &data[..MAGIC.len()] != MAGIC {
+        return Err(invalid_data("Not a cache file"));
+    }
+    if data[MAGIC.len()] != VERSION {
+        return Err(invalid_data("Unsupported version of the cache file"));
+    }
+    let (header, entries) = data.split_at(HEADER_LENGTH);
+    if &header[MAGIC.len() + 1..] != digest(&SHA256, entries).as_ref() {
+        return Err(invalid_data("Cache file is corrupted"));
+    }
+
+    let mut reader = Reader { data: entries };
+    let mut decoded_entries = Vec::new();
+    while !reader.data.is_empty() {
+        match decode_entry(&mut reader, router) {
+            Some(entry) => decoded_entries.push(entry),
+            None => return Err(invalid_data("Cache file is corrupted")),
+        }
+    }
+
+    let now = Instant::now();
+    let system_now = SystemTime::now();
+    let mut n = 0;
+    for entry in decoded_entries {
+        if let Ok(remaining) = entry.expiry.duration_since(system_now) {
+            let expiry = now + remaining;
+            let (cache_key, dns, size) = (entry.cache_key, entry.dns, entry.size);
+            let mut guard_cache = cache.lock(&cache_key);
+            guard_cache.put_with_expiry(cache_key, dns, size, expiry, entry.ttl);
+            n += 1;
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use dns_message_parser::{Class, Dns, RCode, RData, RR};
+
+    use std::env::temp_dir;
+    use std::fs::{remove_file, write};
+    use std::io::ErrorKind;
+    use std::net::Ipv4Addr;
+    use std::process::id;
+    use std::time::{Duration, Instant};
+
+    use super::{encode, load, write_file, HEADER_LENGTH};
+    use crate::dns::{create_response, create_test_request};
+    use crate::edns::Edns;
+    use crate::forward::Router;
+    use crate::{CacheKey, ShardedCache};
+
+    fn create_entry(domain_name: &str, edns: Option<Edns>) -> (CacheKey, Dns) {
+        let dns_request = create_test_request(domain_name);
+        let question = &dns_request.questions[0];
+        let cache_key = CacheKey::new(0, question.clone(), &dns_request.flags, edns);
+        let mut dns = create_response(&dns_request, RCode::NoError);
+        dns.id = 1234;
+        let domain_name = question.get_domain_name().clone();
+        let rdata = RData::A(Ipv4Addr::new(192, 0, 2, 1));
+        dns.answers
+            .push(RR::new(domain_name, Class::IN, 3600, rdata));
+        (cache_key, dns)
+    }
+
+    #[test]
+    fn test_save_load() {
+        let path = temp_dir().join(format!("doh-client-test-{}.cache", id()));
+        let path = path.to_str().unwrap();
+        let router = Router::new(&[]);
+
+        let cache = ShardedCache::new(1024, None, 0);
+        let (cache_key_1, dns_1) = create_entry("example.com", None);
+        let edns = Edns {
+            dnssec_ok: true,
+            client_subnet: Some(vec![0, 1, 24, 192, 0, 2]),
+        };
+        let (cache_key_2, dns_2) = create_entry("example.org", Some(edns));
+        let (cache_key_3, dns_3) = create_entry("example.net", None);
+        cache
+            .lock(&cache_key_1)
+            .put(cache_key_1.clone(), dns_1, 0, Duration::from_secs(3600));
+        cache
+            .lock(&cache_key_2)
+            .put(cache_key_2.clone(), dns_2, 0, Duration::from_secs(3600));
+        cache
+            .lock(&cache_key_3)
+            .put(cache_key_3.clone(), dns_3, 0, Duration::from_secs(0));
+        let (bytes, n) = encode(&cache);
+        assert_eq!(n, 2);
+        write_file(path, &bytes).unwrap();
+
+        let cache = ShardedCache::new(1024, None, 0);
+        assert_eq!(load(&cache, &router, path).unwrap(), 2);
+        assert!(cache.lock(&cache_key_1).get(&cache_key_1).is_some());
+        assert!(cache.lock(&cache_key_2).get(&cache_key_2).is_some());
+        assert!(cache.lock(&cache_key_3).get(&cache_key_3).is_none());
+        assert!(cache.lock(&cache_key_1).age(&cache_key_1).unwrap() < Duration::from_secs(1));
+
+        let mut corrupted = bytes.to_vec();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0xff;
+        write(path, &corrupted).unwrap();
+        let cache = ShardedCache::new(1024, None, 0);
+        let e = load(&cache, &router, path).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert!(cache.lock(&cache_key_1).get(&cache_key_1).is_none());
+
+        let mut version = bytes.to_vec();
+        version[4] += 1;
+        write(path, &version).unwrap();
+        let e = load(&cache, &router, path).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+        write(path, &bytes[..HEADER_LENGTH - 1]).unwrap();
+        let e = load(&cache, &router, path).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_save_load_age() {
+        let path = temp_dir().join(format!("doh-client-test-age-{}.cache", id()));
+        let path = path.to_str().unwrap();
+        let router = Router::new(&[]);
+
+        // The entry was received long before this process was started, so the time of its
+        // insertion cannot be represented as an `Instant` after loading it.
+        let ttl = Duration::from_secs(100 * 365 * 24 * 3600);
+        let remaining = Duration::from_secs(3600);
+        let cache = ShardedCache::new(1024, None, 0);
+        let (cache_key, dns) = create_entry("example.com", None);
+        let expiry = Instant::now() + remaining;
+        cache
+            .lock(&cache_key)
+            .put_with_expiry(cache_key.clone(), dns, 0, expiry, ttl);
+        let (bytes, n) = encode(&cache);
+        assert_eq!(n, 1);
+        write_file(path, &bytes).unwrap();
+
+        let cache = ShardedCache::new(1024, None, 0);
+        assert_eq!(load(&cache, &router, path).unwrap(), 1);
+        let age = cache.lock(&cache_key).age(&cache_key).unwrap();
+        assert!(age >= ttl - remaining);
+        assert!(age < ttl - remaining + Duration::from_secs(1));
+
+        remove_file(path).unwrap();
+    }
+}
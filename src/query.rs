@@ -0,0 +1,236 @@
+use crate::dns::create_request;
+use crate::{Config, DohResult};
+
+use dns_message_parser::{Dns, Question};
+
+use http::response::Parts;
+
+use std::fmt::{Display, Write};
+use std::time::{Duration, Instant};
+
+/// The headers of the HTTP response, which are shown.
+const HEADERS: [&str; 2] = ["cache-control", "server"];
+
+/// The result of `doh-client query`.
+struct QueryResult {
+    server: String,
+    header: Parts,
+    dns: Dns,
+    connect_time: Duration,
+    query_time: Duration,
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Escape the string `s`, so that it can be used as a JSON string.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn escape_slice<T: Display>(slice: &[T]) -> String {
+    let items: Vec<String> = slice.iter().map(|t| escape(&t.to_string())).collect();
+    format!("[{}]", items.join(", "))
+}
+
+impl QueryResult {
+    fn get_headers(&self) -> Vec<(&'static str, String)> {
+        HEADERS
+            .iter()
+            .filter_map(|name| {
+                let value = self.header.headers.get(*name)?;
+                Some((*name, String::from_utf8_lossy(value.as_bytes()).to_string()))
+            })
+            .collect()
+    }
+
+    fn to_text(&self) -> String {
+        let mut output = String::new();
+        let _ = writeln!(output, ";; Server: {}", self.server);
+        let _ = writeln!(output, ";; HTTP status: {}", self.header.status);
+        for (name, value) in self.get_headers() {
+            let _ = writeln!(output, ";; {}: {}", name, value);
+        }
+        let connect_time = as_millis(self.connect_time);
+        let query_time = as_millis(self.query_time);
+        let _ = writeln!(output, ";; Connect time: {:.1} ms", connect_time);
+        let _ = writeln!(output, ";; Query time: {:.1} ms", query_time);
+        let _ = writeln!(
+            output,
+            ";; Status: {:?}, flags: {}",
+            self.dns.flags.rcode, self.dns.flags
+        );
+        let sections: [(&str, Vec<String>); 4] = [
+            ("QUESTION", to_strings(&self.dns.questions)),
+            ("ANSWER", to_strings(&self.dns.answers)),
+            ("AUTHORITY", to_strings(&self.dns.authorities)),
+            ("ADDITIONAL", to_strings(&self.dns.additionals)),
+        ];
+        for (name, items) in sections.iter() {
+            if items.is_empty() {
+                continue;
+            }
+            let _ = writeln!(output, "\n;; {} SECTION:", name);
+            for item in items {
+                let _ = writeln!(output, "{}", item);
+            }
+        }
+        output
+    }
+
+    fn to_json(&self) -> String {
+        let headers: Vec<String> = self
+            .get_headers()
+            .iter()
+            .map(|(name, value)| format!("{}: {}", escape(name), escape(value)))
+            .collect();
+        let dns = &self.dns;
+        format!(
+            "{{\"server\": {}, \"status\": {}, \"headers\": {{{}}}, \"connect_time_ms\": {:.3}, \
+            \"query_time_ms\": {:.3}, \"rcode\": {}, \"flags\": {}, \"questions\": {}, \
+            \"answers\": {}, \"authorities\": {}, \"additionals\": {}}}\n",
+            escape(&self.server),
+            self.header.status.as_u16(),
+            headers.join(", "),
+            as_millis(self.connect_time),
+            as_millis(self.query_time),
+            escape(&format!("{:?}", dns.flags.rcode)),
+            escape(&dns.flags.to_string()),
+            escape_slice(&dns.questions),
+            escape_slice(&dns.answers),
+            escape_slice(&dns.authorities),
+            escape_slice(&dns.additionals),
+        )
+    }
+}
+
+fn to_strings<T: Display>(slice: &[T]) -> Vec<String> {
+    slice.iter().map(|t| t.to_string()).collect()
+}
+
+/// Send a single DNS request with the question `question` to the DoH server of `config` and
+/// return the response, the HTTP status, some headers and the timing as text or JSON.
+pub async fn query(config: Config, question: Question, json: bool) -> DohResult<String> {
+    let mut session = config.into_remote_session();
+    let mut dns_request = create_request(question);
+
+    let start = Instant::now();
+    session.resolve().await;
+    session.connect().await?;
+    let connect_time = start.elapsed();
+
+    let start = Instant::now();
+    let (header, dns) = session.query(&mut dns_request).await?;
+    let query_time = start.elapsed();
+
+    let result = QueryResult {
+        server: session.to_string(),
+        header,
+        dns,
+        connect_time,
+        query_time,
+    };
+    if json {
+        Ok(result.to_json())
+    } else {
+        Ok(result.to_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dns_message_parser::{DomainName, Question, Type};
+
+    use std::convert::TryFrom;
+
+    use super::{escape, query};
+    use crate::remote::mock::{start, DOMAIN};
+    use crate::{
+        CacheOptions, Config, ListenConfig, RemoteHost, RemoteOptions, TlsOptions, TtlOptions,
+    };
+
+    async fn create_config(post: bool) -> Config {
+        let remote_addr = start().await;
+        let remote = RemoteOptions {
+            host: RemoteHost::Direct(vec![remote_addr]),
+            domain: DOMAIN.to_string(),
+            path: "dns-query".to_string(),
+            retries: 1,
+            timeout: 2,
+            connect_timeout: 2,
+            handshake_timeout: 2,
+            keepalive: 0,
+            post,
+            bootstrap: None,
+        };
+        let tls = TlsOptions {
+            cafiles: vec![concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs/ca.pem").to_string()],
+            cadirs: Vec::new(),
+            pins: Vec::new(),
+            backup_pins: Vec::new(),
+            client_cert: None,
+        };
+        let cache = CacheOptions {
+            size: 0,
+            max_bytes: 0,
+            prefetch: 0,
+            file: None,
+            save_interval: 0,
+            fallback: None,
+        };
+        let ttl = TtlOptions {
+            min: 0,
+            max: 0,
+            rewrite: false,
+            negative_max: 3600,
+        };
+        let listen_config = ListenConfig::Addr("127.0.0.1:0".parse().unwrap());
+        Config::new(listen_config, None, remote, tls, cache, ttl, Vec::new()).unwrap()
+    }
+
+    fn create_question() -> Question {
+        let domain_name = DomainName::try_from("example.com").unwrap();
+        crate::dns::create_question(domain_name, Type::A)
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let output = query(create_config(true).await, create_question(), false)
+            .await
+            .unwrap();
+        assert!(output.contains(";; HTTP status: 200 OK"));
+        assert!(output.contains(";; server: mock"));
+        assert!(output.contains("192.0.2.1"));
+
+        let output = query(create_config(false).await, create_question(), true)
+            .await
+            .unwrap();
+        assert!(output.starts_with('{'));
+        assert!(output.contains("\"status\": 200"));
+        assert!(output.contains("\"rcode\": \"NoError\""));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("example.com."), "\"example.com.\"");
+        assert_eq!(escape("\"a\\b\"\n"), "\"\\\"a\\\\b\\\"\\n\"");
+        assert_eq!(escape("\u{1}"), "\"\\u0001\"");
+    }
+}
@@ -0,0 +1,133 @@
+use ring::rand::{SecureRandom, SystemRandom};
+
+use std::cmp::min;
+use std::time::{Duration, Instant};
+
+/// The delay before the first reconnect attempt.
+const BACKOFF_BASE: Duration = Duration::from_millis(100);
+
+/// The maximum delay between two reconnect attempts.
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// Get a random factor between 0.5 and 1.0.
+fn get_jitter() -> f64 {
+    let mut random = [0; 4];
+    if SystemRandom::new().fill(&mut random).is_err() {
+        return 1.0;
+    }
+    let random = u32::from_be_bytes(random) as f64 / u32::MAX as f64;
+    0.5 + random / 2.0
+}
+
+/// An exponential backoff with jitter.
+pub(super) struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    pub(super) fn new() -> Backoff {
+        Backoff { attempt: 0 }
+    }
+
+    fn get_max_delay(&self) -> Duration {
+        let factor = 1u32.checked_shl(self.attempt).unwrap_or(u32::MAX);
+        match BACKOFF_BASE.checked_mul(factor) {
+            Some(delay) => min(delay, BACKOFF_MAX),
+            None => BACKOFF_MAX,
+        }
+    }
+
+    /// Get the delay before the next attempt, which is doubled with every attempt.
+    pub(super) fn next_delay(&mut self) -> Duration {
+        let delay = self.get_max_delay().mul_f64(get_jitter());
+        self.attempt = self.attempt.saturating_add(1);
+        delay
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// A circuit breaker for the connection to the upstream.
+///
+/// After all reconnect attempts failed, the circuit is open and requests fail fast without trying
+/// to connect. After the backoff delay, the circuit is half-open and one request may try to
+/// connect again. If it fails, then the circuit is open again for a longer time.
+pub(super) struct CircuitBreaker {
+    backoff: Backoff,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub(super) fn new() -> CircuitBreaker {
+        CircuitBreaker {
+            backoff: Backoff::new(),
+            open_until: None,
+        }
+    }
+
+    pub(super) fn is_open(&self) -> bool {
+        match self.open_until {
+            Some(open_until) => Instant::now() < open_until,
+            None => false,
+        }
+    }
+
+    pub(super) fn is_half_open(&self) -> bool {
+        match self.open_until {
+            Some(open_until) => open_until <= Instant::now(),
+            None => false,
+        }
+    }
+
+    pub(super) fn success(&mut self) {
+        self.backoff.reset();
+        self.open_until = None;
+    }
+
+    pub(super) fn failure(&mut self) -> Duration {
+        let delay = self.backoff.next_delay();
+        self.open_until = Some(Instant::now() + delay);
+        delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::{Backoff, CircuitBreaker, BACKOFF_BASE, BACKOFF_MAX};
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new();
+        for i in 0..64 {
+            let max_delay = BACKOFF_MAX.min(BACKOFF_BASE * 2u32.saturating_pow(i));
+            let delay = backoff.next_delay();
+            assert!(delay <= max_delay);
+            assert!(delay >= max_delay / 2);
+        }
+        backoff.reset();
+        assert!(backoff.next_delay() <= BACKOFF_BASE);
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let mut circuit_breaker = CircuitBreaker::new();
+        assert!(!circuit_breaker.is_open());
+        assert!(!circuit_breaker.is_half_open());
+
+        let delay = circuit_breaker.failure();
+        assert!(circuit_breaker.is_open());
+        assert!(!circuit_breaker.is_half_open());
+
+        sleep(delay);
+        assert!(!circuit_breaker.is_open());
+        assert!(circuit_breaker.is_half_open());
+
+        circuit_breaker.success();
+        assert!(!circuit_breaker.is_open());
+        assert!(!circuit_breaker.is_half_open());
+    }
+}
@@ -0,0 +1,290 @@
+use crate::dns::{create_question, create_request};
+use crate::{DohError, DohResult};
+
+use dns_message_parser::{Dns, DomainError, DomainName, RData, Type};
+
+use futures::future::join;
+use futures::lock::Mutex;
+
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex as StdMutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use tokio::spawn;
+use tokio::time::delay_for;
+
+use super::{Backoff, PlainSession};
+
+/// The number of consecutive failed connection attempts, after that the hostname is resolved
+/// again, even if the addresses are not expired.
+const MAX_CONNECT_FAILURES: u32 = 2;
+
+/// The time to live of the addresses if the response does not contain any.
+const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+fn get_remote_addrs(dns_response: &Dns, port: u16, remote_addrs: &mut Vec<SocketAddr>) {
+    for answer in &dns_response.answers {
+        match answer.get_rdata() {
+            RData::A(ipv4_addr) => remote_addrs.push(SocketAddr::new((*ipv4_addr).into(), port)),
+            RData::AAAA(ipv6_addr) => remote_addrs.push(SocketAddr::new((*ipv6_addr).into(), port)),
+            _ => {}
+        }
+    }
+}
+
+/// The addresses of the hostname and the state of their resolution.
+struct State {
+    remote_addrs: Vec<SocketAddr>,
+    /// The number of successful resolutions, so that a session can detect new addresses.
+    generation: u32,
+    expiry: Option<Instant>,
+    connect_failures: u32,
+    /// Whether the hostname is resolved in the background.
+    refreshing: bool,
+}
+
+/// A resolver for the hostname of the DoH server.
+///
+/// The hostname is resolved with plain DNS servers instead of the system resolver, which could be
+/// the `doh-client` itself. The addresses are resolved again after their TTL is expired or if no
+/// connection could be established to them. Until then, the last addresses are used.
+///
+/// The resolver is shared with the session, so that the hostname can be resolved without locking
+/// the session.
+pub struct Bootstrap {
+    hostname: DomainName,
+    port: u16,
+    remote_addrs: Vec<SocketAddr>,
+    state: StdMutex<State>,
+    resolving: Mutex<()>,
+}
+
+impl Bootstrap {
+    /// Create a new `doh_client::Bootstrap` object, which resolves `hostname` with the plain DNS
+    /// servers `remote_addrs`.
+    pub fn new(
+        hostname: &str,
+        port: u16,
+        remote_addrs: Vec<SocketAddr>,
+    ) -> Result<Bootstrap, DomainError> {
+        let hostname = DomainName::try_from(hostname)?;
+        let state = State {
+            remote_addrs: Vec::new(),
+            generation: 0,
+            expiry: None,
+            connect_failures: 0,
+            refreshing: false,
+        };
+        Ok(Bootstrap {
+            hostname,
+            port,
+            remote_addrs,
+            state: StdMutex::new(state),
+            resolving: Mutex::new(()),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Whether the hostname was resolved at least once.
+    fn is_resolved(&self) -> bool {
+        !self.state().remote_addrs.is_empty()
+    }
+
+    pub(super) fn is_expired(&self) -> bool {
+        match self.state().expiry {
+            Some(expiry) => expiry <= Instant::now(),
+            None => true,
+        }
+    }
+
+    /// Get the addresses, if they were resolved after `generation`, and update `generation`.
+    pub(super) fn get_remote_addrs(&self, generation: &mut u32) -> Option<Vec<SocketAddr>> {
+        let state = self.state();
+        if state.generation == *generation {
+            return None;
+        }
+        *generation = state.generation;
+        Some(state.remote_addrs.clone())
+    }
+
+    pub(super) fn connected(&self) {
+        self.state().connect_failures = 0;
+    }
+
+    pub(super) fn could_not_connect(&self) {
+        let mut state = self.state();
+        state.connect_failures += 1;
+        if state.connect_failures >= MAX_CONNECT_FAILURES {
+            debug!("Addresses of {} are expired", self.hostname);
+            state.expiry = None;
+        }
+    }
+
+    /// Resolve the A and AAAA records of the hostname.
+    pub(super) async fn resolve(
+        &self,
+        retries: u32,
+        timeout: Duration,
+    ) -> DohResult<Vec<SocketAddr>> {
+        let mut session = PlainSession::new(self.remote_addrs.clone(), retries, timeout);
+        let mut dns_request_a = create_request(create_question(self.hostname.clone(), Type::A));
+        let mut dns_request_aaaa =
+            create_request(create_question(self.hostname.clone(), Type::AAAA));
+        let (response_a, _) = session.start_request(&mut dns_request_a)?;
+        let (response_aaaa, _) = session.start_request(&mut dns_request_aaaa)?;
+        let (result_a, result_aaaa) = join(response_a, response_aaaa).await;
+
+        let mut remote_addrs = Vec::new();
+        let mut ttl: Option<Duration> = None;
+        for result in [result_aaaa, result_a] {
+            match result {
+                Ok((dns_response, duration)) => {
+                    get_remote_addrs(&dns_response, self.port, &mut remote_addrs);
+                    if let Some(duration) = duration {
+                        ttl = Some(ttl.map_or(duration, |ttl| ttl.min(duration)));
+                    }
+                }
+                Err(e) => error!("Could not resolve {}: {}", self.hostname, e),
+            }
+        }
+
+        if remote_addrs.is_empty() {
+            return Err(DohError::CouldNotResolve(self.hostname.to_string()));
+        }
+
+        let ttl = ttl.unwrap_or(DEFAULT_TTL);
+        info!("Resolved {} to {:?} for {:?}", self, remote_addrs, ttl);
+        let mut state = self.state();
+        state.remote_addrs = remote_addrs.clone();
+        state.generation = state.generation.wrapping_add(1);
+        state.expiry = Some(Instant::now() + ttl);
+        state.connect_failures = 0;
+        Ok(remote_addrs)
+    }
+
+    /// Resolve the hostname, if the addresses are expired.
+    ///
+    /// Only if the hostname was never resolved, the call waits for the resolution. Concurrent calls
+    /// wait for the first one, so that the hostname is resolved only once. Otherwise the last
+    /// addresses are used until the hostname is resolved in the background.
+    pub(super) async fn refresh(self: Arc<Self>, retries: u32, timeout: Duration) {
+        if !self.is_resolved() {
+            let _resolving = self.resolving.lock().await;
+            if !self.is_resolved() {
+                if let Err(e) = self.resolve(retries, timeout).await {
+                    error!("Could not resolve {}: {}", self, e);
+                }
+            }
+        } else if self.is_expired() {
+            let mut state = self.state();
+            if !state.refreshing {
+                state.refreshing = true;
+                drop(state);
+                spawn(async move { self.refresh_in_background(retries, timeout).await });
+            }
+        }
+    }
+
+    /// Resolve the hostname until it succeeds, where the attempts are delayed by a backoff.
+    async fn refresh_in_background(&self, retries: u32, timeout: Duration) {
+        let mut backoff = Backoff::new();
+        loop {
+            let resolving = self.resolving.lock().await;
+            match self.resolve(retries, timeout).await {
+                Ok(_) => break,
+                Err(e) => {
+                    let delay = backoff.next_delay();
+                    error!(
+                        "Could not resolve {}, try again in {:?}: {}",
+                        self, delay, e
+                    );
+                    drop(resolving);
+                    delay_for(delay).await;
+                }
+            }
+        }
+        self.state().refreshing = false;
+    }
+}
+
+impl Display for Bootstrap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "{}:{} via {:?}",
+            self.hostname, self.port, self.remote_addrs
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use dns_message_parser::{Class, Dns, QType, RData, Type, RR};
+
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::net::UdpSocket;
+    use tokio::spawn;
+
+    use super::Bootstrap;
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let remote_addr = socket.local_addr().unwrap();
+        spawn(async move {
+            let mut buffer = [0; 512];
+            for _ in 0..2 {
+                let (n, addr) = socket.recv_from(&mut buffer).await.unwrap();
+                let mut dns = Dns::decode(&Bytes::copy_from_slice(&buffer[..n])).unwrap();
+                dns.flags.qr = true;
+                let domain_name = dns.questions[0].get_domain_name().clone();
+                let rdata = if dns.questions[0].get_qtype() == &QType::Type(Type::A) {
+                    RData::A(Ipv4Addr::new(192, 0, 2, 1))
+                } else {
+                    RData::AAAA(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))
+                };
+                dns.answers.push(RR::new(domain_name, Class::IN, 60, rdata));
+                let bytes = dns.to_bytes().unwrap();
+                socket.send_to(&bytes, &addr).await.unwrap();
+            }
+        });
+
+        let bootstrap =
+            Arc::new(Bootstrap::new("dns.example.com", 443, vec![remote_addr]).unwrap());
+        assert!(bootstrap.is_expired());
+        let remote_addrs = bootstrap.resolve(1, Duration::from_secs(2)).await.unwrap();
+        assert!(!bootstrap.is_expired());
+        assert_eq!(
+            remote_addrs,
+            vec![
+                SocketAddr::new(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into(), 443),
+                SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 443)
+            ]
+        );
+
+        let mut generation = 0;
+        assert_eq!(
+            bootstrap.get_remote_addrs(&mut generation),
+            Some(remote_addrs)
+        );
+        assert_eq!(bootstrap.get_remote_addrs(&mut generation), None);
+        // The addresses are not expired, so they are not resolved again.
+        bootstrap.clone().refresh(1, Duration::from_secs(2)).await;
+        assert_eq!(bootstrap.get_remote_addrs(&mut generation), None);
+
+        bootstrap.could_not_connect();
+        assert!(!bootstrap.is_expired());
+        bootstrap.could_not_connect();
+        assert!(bootstrap.is_expired());
+    }
+}
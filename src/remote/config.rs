@@ -1,29 +1,19 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use rustls::ClientConfig;
 
-pub(super) struct Config {
-    pub(super) domain: String,
-    pub(super) client_config: Arc<ClientConfig>,
-    pub(super) uri: String,
-    pub(super) retries: u32,
-    pub(super) post: bool,
-}
-
-impl Config {
-    pub(super) fn new(
-        domain: String,
-        client_config: Arc<ClientConfig>,
-        uri: String,
-        retries: u32,
-        post: bool,
-    ) -> Config {
-        Config {
-            domain,
-            client_config,
-            uri,
-            retries,
-            post,
-        }
-    }
+/// The configuration of a session to the DoH server.
+pub(crate) struct Config {
+    /// The hostname of the server, which is used for SNI and the certificate validation.
+    pub(crate) domain: String,
+    pub(crate) client_config: Arc<ClientConfig>,
+    pub(crate) uri: String,
+    pub(crate) retries: u32,
+    pub(crate) timeout: Duration,
+    pub(crate) connect_timeout: Duration,
+    pub(crate) handshake_timeout: Duration,
+    pub(crate) keepalive: Option<Duration>,
+    /// Send the requests with POST instead of GET.
+    pub(crate) post: bool,
 }
@@ -2,24 +2,21 @@ use bytes::Bytes;
 
 use crate::{DohError, DohResult};
 
-use h2::client::{ResponseFuture, SendRequest};
+use h2::client::ResponseFuture;
 use h2::SendStream;
 
 use http::Request;
 
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::net::SocketAddr;
-use std::sync::Arc;
 
 #[cfg(feature = "socks5")]
-use super::socks5_connect;
-use super::{http2_connect, tcp_connect, try_tls_connect};
+use super::socks5_tls_connect;
+use super::{http2_connect, tls_connect, Config, Http2Connection};
 
 #[cfg(feature = "socks5")]
 use tokio_socks::TargetAddr;
 
-use rustls::ClientConfig;
-
 macro_rules! send_request_option {
     ($self:ident) => {
         match $self {
@@ -31,13 +28,13 @@ macro_rules! send_request_option {
 }
 
 pub(super) enum Connection {
-    Direct(Vec<SocketAddr>, Option<SendRequest<Bytes>>),
+    Direct(Vec<SocketAddr>, Option<Http2Connection>),
     #[cfg(feature = "socks5")]
     Socks5(
         Vec<SocketAddr>,
         Option<(String, String)>,
         Vec<TargetAddr<'static>>,
-        Option<SendRequest<Bytes>>,
+        Option<Http2Connection>,
     ),
 }
 
@@ -63,28 +60,37 @@ impl Connection {
         }
     }
 
-    pub(super) fn is_connected(&self) -> bool {
+    pub(super) fn set_remote_addrs(&mut self, new_remote_addrs: Vec<SocketAddr>) {
         match self {
-            Connection::Direct(_, send_request) => send_request.is_some(),
+            Connection::Direct(remote_addrs, _) => *remote_addrs = new_remote_addrs,
             #[cfg(feature = "socks5")]
-            Connection::Socks5(_, _, _, send_request) => send_request.is_some(),
+            Connection::Socks5(_, _, dest_addrs, _) => {
+                *dest_addrs = new_remote_addrs.into_iter().map(TargetAddr::Ip).collect()
+            }
         }
     }
 
-    pub(super) async fn connect(
-        &mut self,
-        client_config: &Arc<ClientConfig>,
-        domain: &str,
-    ) -> DohResult<()> {
+    pub(super) fn is_connected(&mut self) -> bool {
+        let send_request = send_request_option!(self);
+        let is_closed = match send_request {
+            Some(http2_connection) => http2_connection.is_closed(),
+            None => return false,
+        };
+        if is_closed {
+            send_request.take();
+        }
+        !is_closed
+    }
+
+    pub(super) async fn connect(&mut self, config: &Config) -> DohResult<()> {
         match self {
             Connection::Direct(remote_addrs, send_request) => {
                 if send_request.is_some() {
                     return Ok(());
                 }
 
-                let tcp_connection = tcp_connect(remote_addrs).await?;
-                let tls_connection = try_tls_connect(tcp_connection, client_config, domain).await?;
-                let http2_connection = http2_connect(tls_connection).await?;
+                let tls_connection = tls_connect(remote_addrs, config).await?;
+                let http2_connection = http2_connect(tls_connection, config).await?;
                 send_request.replace(http2_connection);
             }
             #[cfg(feature = "socks5")]
@@ -93,9 +99,9 @@ impl Connection {
                     return Ok(());
                 }
 
-                let tcp_connection = socks5_connect(remote_addrs, dest_addrs, credentials).await?;
-                let tls_connection = try_tls_connect(tcp_connection, client_config, domain).await?;
-                let http2_connection = http2_connect(tls_connection).await?;
+                let tls_connection =
+                    socks5_tls_connect(remote_addrs, dest_addrs, credentials, config).await?;
+                let http2_connection = http2_connect(tls_connection, config).await?;
                 send_request.replace(http2_connection);
             }
         }
@@ -110,10 +116,12 @@ impl Connection {
     pub(super) async fn send_request(
         &mut self,
         request: Request<()>,
+        end_of_stream: bool,
     ) -> DohResult<(ResponseFuture, SendStream<Bytes>)> {
         let send_request = send_request_option!(self);
-        if let Some(send_request) = send_request {
-            Ok(send_request.send_request(request, false)?)
+        if let Some(http2_connection) = send_request {
+            let send_request = http2_connection.send_request();
+            Ok(send_request.send_request(request, end_of_stream)?)
         } else {
             Err(DohError::IsNotConnected)
         }
@@ -0,0 +1,157 @@
+use futures::future::FutureExt;
+use futures::select;
+use futures::stream::{FuturesUnordered, StreamExt};
+
+use std::fmt::{Debug, Display};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::time::delay_for;
+
+/// The delay between two connection attempts (see RFC 8305 section 5).
+pub(super) const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Sort the addresses, so that IPv6 and IPv4 addresses alternate (see RFC 8305 section 4).
+///
+/// The family of the first address is tried first and the order within a family is kept.
+pub(super) fn interleave(remote_addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let first_is_ipv6 = match remote_addrs.first() {
+        Some(remote_addr) => remote_addr.is_ipv6(),
+        None => return Vec::new(),
+    };
+    let (mut first, mut second): (Vec<SocketAddr>, Vec<SocketAddr>) = remote_addrs
+        .iter()
+        .partition(|remote_addr| remote_addr.is_ipv6() == first_is_ipv6);
+    let mut first = first.drain(..);
+    let mut second = second.drain(..);
+
+    let mut result = Vec::with_capacity(remote_addrs.len());
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return result,
+            (a, b) => {
+                result.extend(a);
+                result.extend(b);
+            }
+        }
+    }
+}
+
+/// Race the connection attempts to the `candidates`.
+///
+/// The attempts are started in order, each `delay` after the previous one or immediately if the
+/// previous one failed. The first successful attempt wins, all other attempts are cancelled.
+/// Returns `None` if all attempts failed.
+pub(super) async fn race<C, F, Fut, T, E>(
+    candidates: Vec<C>,
+    delay: Duration,
+    connect: F,
+) -> Option<T>
+where
+    C: Debug,
+    F: Fn(C) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Display,
+{
+    let mut candidates = candidates.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let start = |candidate: C| {
+        let candidate_str = format!("{:?}", candidate);
+        debug!("Start connection attempt to {}", candidate_str);
+        connect(candidate).map(move |result| (candidate_str, result))
+    };
+
+    if let Some(candidate) = candidates.next() {
+        attempts.push(start(candidate));
+    }
+
+    while !attempts.is_empty() {
+        let mut next_attempt = delay_for(delay).fuse();
+        select! {
+            attempt = attempts.next() => match attempt {
+                Some((_, Ok(connection))) => return Some(connection),
+                Some((candidate, Err(e))) => error!("Could not connect to {}: {}", candidate, e),
+                None => {}
+            },
+            _ = next_attempt => {},
+        }
+
+        if let Some(candidate) = candidates.next() {
+            attempts.push(start(candidate));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::pending;
+
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    use super::{interleave, race};
+
+    #[test]
+    fn test_interleave() {
+        let remote_addrs: Vec<SocketAddr> = vec![
+            "[2001:db8::1]:443".parse().unwrap(),
+            "[2001:db8::2]:443".parse().unwrap(),
+            "[2001:db8::3]:443".parse().unwrap(),
+            "192.0.2.1:443".parse().unwrap(),
+            "192.0.2.2:443".parse().unwrap(),
+        ];
+        let result: Vec<SocketAddr> = vec![
+            "[2001:db8::1]:443".parse().unwrap(),
+            "192.0.2.1:443".parse().unwrap(),
+            "[2001:db8::2]:443".parse().unwrap(),
+            "192.0.2.2:443".parse().unwrap(),
+            "[2001:db8::3]:443".parse().unwrap(),
+        ];
+        assert_eq!(interleave(&remote_addrs), result);
+    }
+
+    #[tokio::test]
+    async fn test_race_blackhole() {
+        let start = Instant::now();
+        let delay = Duration::from_millis(100);
+        let result = race(vec![1, 2], delay, |candidate| async move {
+            if candidate == 1 {
+                pending::<()>().await;
+            }
+            Ok::<i32, String>(candidate)
+        })
+        .await;
+        assert_eq!(result, Some(2));
+        assert!(start.elapsed() >= delay);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_race_failure() {
+        let start = Instant::now();
+        let result = race(
+            vec![1, 2, 3],
+            Duration::from_secs(10),
+            |candidate| async move {
+                if candidate == 3 {
+                    Ok(candidate)
+                } else {
+                    Err(format!("{} failed", candidate))
+                }
+            },
+        )
+        .await;
+        assert_eq!(result, Some(3));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let result = race(
+            vec![1, 2],
+            Duration::from_secs(10),
+            |candidate| async move { Err::<i32, i32>(candidate) },
+        )
+        .await;
+        assert_eq!(result, None);
+    }
+}
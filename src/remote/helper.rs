@@ -1,19 +1,14 @@
-use bytes::Bytes;
-
 use crate::{DohError, DohResult};
 
-use h2::client::{handshake, SendRequest};
+use h2::client::handshake;
 
 use std::io::Result as IoResult;
 use std::net::SocketAddr;
-use std::sync::Arc;
 use std::time::Duration;
 
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
-use tokio::spawn;
-
-use rustls::ClientConfig;
+use tokio::time::timeout as create_timeout;
 
 use tokio_rustls::client::TlsStream;
 use tokio_rustls::TlsConnector;
@@ -24,94 +19,118 @@ use tokio_socks::TargetAddr;
 
 use webpki::DNSNameRef;
 
+use super::happy_eyeballs::{interleave, race, CONNECTION_ATTEMPT_DELAY};
+use super::{get_tls_error, Config, Http2Connection};
+
 fn set_tcp_option(tcp_connection: &mut TcpStream) -> IoResult<()> {
     tcp_connection.set_keepalive(Some(Duration::from_secs(1)))?;
     tcp_connection.set_nodelay(true)?;
     Ok(())
 }
 
-pub(super) async fn http2_connect<T>(connection: T) -> DohResult<SendRequest<Bytes>>
+pub(super) async fn http2_connect<T>(connection: T, config: &Config) -> DohResult<Http2Connection>
 where
     T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     debug!("HTTP2 handshake");
-    let (send_request, connection) = handshake(connection).await?;
-    spawn(async move {
-        if let Err(e) = connection.await {
-            error!("HTTP2 connection close: {}", e);
-        }
-    });
-    Ok(send_request)
+    let (send_request, connection) =
+        create_timeout(config.handshake_timeout, handshake(connection)).await??;
+    Ok(Http2Connection::new(
+        send_request,
+        connection,
+        config.keepalive,
+        config.timeout,
+    ))
 }
 
-pub(super) async fn try_tls_connect<T>(
-    connection: T,
-    config: &Arc<ClientConfig>,
-    domain: &str,
-) -> IoResult<TlsStream<T>>
+async fn try_tls_connect<T>(connection: T, config: &Config) -> DohResult<TlsStream<T>>
 where
     T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    let tls_connector = TlsConnector::from(config.clone());
-    tls_connector
-        .connect(DNSNameRef::try_from_ascii_str(domain).unwrap(), connection)
-        .await
+    let tls_connector = TlsConnector::from(config.client_config.clone());
+    let domain = DNSNameRef::try_from_ascii_str(&config.domain).unwrap();
+    let tls_connection = tls_connector.connect(domain, connection);
+    match create_timeout(config.handshake_timeout, tls_connection).await? {
+        Ok(tls_connection) => Ok(tls_connection),
+        Err(e) => Err(get_tls_error(e)),
+    }
 }
 
-async fn try_tcp_connect(remote_addr: &SocketAddr) -> IoResult<TcpStream> {
-    let mut tcp_connection = TcpStream::connect(remote_addr).await?;
+async fn try_tcp_connect(remote_addr: &SocketAddr, config: &Config) -> DohResult<TcpStream> {
+    let tcp_connection = TcpStream::connect(remote_addr);
+    let mut tcp_connection = create_timeout(config.connect_timeout, tcp_connection).await??;
     set_tcp_option(&mut tcp_connection)?;
     Ok(tcp_connection)
 }
 
-pub(super) async fn tcp_connect(remote_addrs: &[SocketAddr]) -> DohResult<TcpStream> {
-    for remote_addr in remote_addrs {
-        match try_tcp_connect(remote_addr).await {
-            Ok(tcp_connection) => return Ok(tcp_connection),
-            Err(e) => {
-                error!("Could not connectio to {}: {}", remote_addr, e);
-                continue;
-            }
-        };
+/// Connect to the first address, which accepts a TCP connection and completes the TLS
+/// handshake (see RFC 8305).
+pub(super) async fn tls_connect(
+    remote_addrs: &[SocketAddr],
+    config: &Config,
+) -> DohResult<TlsStream<TcpStream>> {
+    let candidates = interleave(remote_addrs);
+    let tls_connection = race(
+        candidates,
+        CONNECTION_ATTEMPT_DELAY,
+        |remote_addr| async move {
+            let tcp_connection = try_tcp_connect(&remote_addr, config).await?;
+            try_tls_connect(tcp_connection, config).await
+        },
+    )
+    .await;
+    match tls_connection {
+        Some(tls_connection) => Ok(tls_connection),
+        None => Err(DohError::CouldNotConnect(Vec::from(remote_addrs))),
     }
-    Err(DohError::CouldNotConnect(Vec::from(remote_addrs)))
 }
 #[cfg(feature = "socks5")]
 async fn try_socks5_connect(
     remote_addr: &SocketAddr,
     dest_addr: &TargetAddr<'static>,
     credentials: &Option<(String, String)>,
+    config: &Config,
 ) -> DohResult<TcpStream> {
     let dest_addr = dest_addr.to_owned();
     let socks5_connection = if let Some((username, password)) = credentials {
-        Socks5Stream::connect_with_password(remote_addr, dest_addr, username, password).await
+        let socks5_connection =
+            Socks5Stream::connect_with_password(remote_addr, dest_addr, username, password);
+        create_timeout(config.connect_timeout, socks5_connection).await?
     } else {
-        Socks5Stream::connect(remote_addr, dest_addr).await
+        let socks5_connection = Socks5Stream::connect(remote_addr, dest_addr);
+        create_timeout(config.connect_timeout, socks5_connection).await?
     }?;
     let mut tcp_connection = socks5_connection.into_inner();
     set_tcp_option(&mut tcp_connection)?;
     Ok(tcp_connection)
 }
+/// Connect to the first destination address via the first socks5 proxy, which completes the
+/// TLS handshake (see RFC 8305).
 #[cfg(feature = "socks5")]
-pub(super) async fn socks5_connect(
+pub(super) async fn socks5_tls_connect(
     remote_addrs: &[SocketAddr],
     dest_addrs: &[TargetAddr<'static>],
     credentials: &Option<(String, String)>,
-) -> DohResult<TcpStream> {
-    for remote_addr in remote_addrs {
+    config: &Config,
+) -> DohResult<TlsStream<TcpStream>> {
+    let mut candidates = Vec::with_capacity(remote_addrs.len() * dest_addrs.len());
+    for remote_addr in interleave(remote_addrs) {
         for dest_addr in dest_addrs {
-            let tcp_connection = try_socks5_connect(remote_addr, dest_addr, credentials).await;
-            match tcp_connection {
-                Ok(tcp_connection) => return Ok(tcp_connection),
-                Err(e) => {
-                    error!(
-                        "Could not connect to {:?} via socks5 proxy {}: {}",
-                        dest_addr, remote_addr, e
-                    );
-                    continue;
-                }
-            }
+            candidates.push((remote_addr, dest_addr.to_owned()));
         }
     }
-    Err(DohError::CouldNotConnect(Vec::from(remote_addrs)))
+    let tls_connection = race(
+        candidates,
+        CONNECTION_ATTEMPT_DELAY,
+        |(remote_addr, dest_addr)| async move {
+            let tcp_connection =
+                try_socks5_connect(&remote_addr, &dest_addr, credentials, config).await?;
+            try_tls_connect(tcp_connection, config).await
+        },
+    )
+    .await;
+    match tls_connection {
+        Some(tls_connection) => Ok(tls_connection),
+        None => Err(DohError::CouldNotConnect(Vec::from(remote_addrs))),
+    }
 }
@@ -0,0 +1,164 @@
+use bytes::Bytes;
+
+use crate::DohResult;
+
+use futures::future::{pending, poll_fn, select, Either, FutureExt};
+
+use h2::client::{Connection, SendRequest};
+use h2::{Ping, PingPong};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::spawn;
+use tokio::time::{delay_for, timeout as create_timeout};
+
+/// Send a HTTP2 PING every `interval` and wait `timeout` for the PONG.
+///
+/// Returns an error if the PONG is not received in time. If `interval` is `None`, then no PING is
+/// sent and the future never completes.
+async fn keepalive(
+    ping_pong: Option<PingPong>,
+    interval: Option<Duration>,
+    timeout: Duration,
+) -> DohResult<()> {
+    let (mut ping_pong, interval) = match (ping_pong, interval) {
+        (Some(ping_pong), Some(interval)) => (ping_pong, interval),
+        _ => return pending().await,
+    };
+    loop {
+        delay_for(interval).await;
+        let start = Instant::now();
+        create_timeout(timeout, ping_pong.ping(Ping::opaque())).await??;
+        debug!("HTTP2 PING RTT: {:?}", start.elapsed());
+    }
+}
+
+/// A HTTP2 connection, which is driven by a background task.
+///
+/// The background task sends the PINGs and marks the connection as closed if the connection is
+/// closed or the server does not answer the PINGs anymore.
+pub(super) struct Http2Connection {
+    send_request: SendRequest<Bytes>,
+    closed: Arc<AtomicBool>,
+}
+
+impl Http2Connection {
+    pub(super) fn new<T>(
+        send_request: SendRequest<Bytes>,
+        mut connection: Connection<T, Bytes>,
+        interval: Option<Duration>,
+        timeout: Duration,
+    ) -> Http2Connection
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let closed = Arc::new(AtomicBool::new(false));
+        let closed_task = closed.clone();
+        let ping_pong = connection.ping_pong();
+        spawn(async move {
+            let keepalive = Box::pin(keepalive(ping_pong, interval, timeout));
+            match select(connection, keepalive).await {
+                Either::Left((Ok(()), _)) => debug!("HTTP2 connection closed"),
+                Either::Left((Err(e), _)) => error!("HTTP2 connection close: {}", e),
+                Either::Right((Err(e), _)) => error!("HTTP2 PING failed: {}", e),
+                Either::Right((Ok(()), _)) => {}
+            }
+            closed_task.store(true, Ordering::Relaxed);
+        });
+        Http2Connection {
+            send_request,
+            closed,
+        }
+    }
+
+    /// Check if new requests can be sent over this connection.
+    ///
+    /// After a GOAWAY frame, the requests in flight are finished, but new requests are rejected.
+    /// Therefore, the connection is treated as closed, so that a fresh connection is established.
+    pub(super) fn is_closed(&mut self) -> bool {
+        if self.closed.load(Ordering::Relaxed) {
+            return true;
+        }
+        let send_request = &mut self.send_request;
+        match poll_fn(|cx| send_request.poll_ready(cx)).now_or_never() {
+            Some(Err(e)) => {
+                debug!("HTTP2 connection is going away: {}", e);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(super) fn send_request(&mut self) -> &mut SendRequest<Bytes> {
+        &mut self.send_request
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use h2::client::handshake;
+    use h2::server::handshake as server_handshake;
+
+    use std::time::Duration;
+
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::spawn;
+    use tokio::time::delay_for;
+
+    use super::Http2Connection;
+
+    async fn create_connection(listener: &TcpListener) -> Http2Connection {
+        let tcp_connection = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (send_request, connection) = handshake(tcp_connection).await.unwrap();
+        let interval = Some(Duration::from_millis(50));
+        Http2Connection::new(
+            send_request,
+            connection,
+            interval,
+            Duration::from_millis(100),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_goaway() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut http2_connection = create_connection(&listener).await;
+        let (tcp_connection, _) = listener.accept().await.unwrap();
+        spawn(async move {
+            let mut connection = server_handshake(tcp_connection).await.unwrap();
+            let mut ping = true;
+            loop {
+                tokio::select! {
+                    _ = connection.accept() => break,
+                    _ = delay_for(Duration::from_millis(300)), if ping => {
+                        connection.graceful_shutdown();
+                        ping = false;
+                    }
+                }
+            }
+        });
+
+        delay_for(Duration::from_millis(200)).await;
+        assert!(!http2_connection.is_closed());
+        delay_for(Duration::from_millis(300)).await;
+        assert!(http2_connection.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_timeout() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut http2_connection = create_connection(&listener).await;
+        let (tcp_connection, _) = listener.accept().await.unwrap();
+        // The server does not answer the PINGs.
+        let _tcp_connection = tcp_connection;
+
+        assert!(!http2_connection.is_closed());
+        delay_for(Duration::from_millis(300)).await;
+        assert!(http2_connection.is_closed());
+    }
+}
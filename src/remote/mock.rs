@@ -0,0 +1,207 @@
+//! A local DoH server for the tests, which answers every A question with `192.0.2.1` and every
+//! other question with an empty answer. It can require a client certificate, which is signed by
+//! the CA, or shut down every connection gracefully after the first request.
+
+use base64::{decode_config, URL_SAFE_NO_PAD};
+
+use bytes::{Bytes, BytesMut};
+
+use dns_message_parser::{Class, Dns, QClass, QType, RData, Type, RR};
+
+use h2::server::{handshake, SendResponse};
+use h2::RecvStream;
+
+use http::{Method, Request, Response, StatusCode};
+
+use rustls::internal::pemfile::{certs, pkcs8_private_keys};
+use rustls::{
+    AllowAnyAuthenticatedClient, ClientCertVerifier, ClientConfig, NoClientAuth, RootCertStore,
+    ServerConfig,
+};
+
+use std::io::BufReader;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::spawn;
+use tokio::time::delay_for;
+use tokio_rustls::TlsAcceptor;
+
+use super::SessionConfig;
+
+/// The domain name of the server certificate.
+pub(crate) const DOMAIN: &str = "localhost";
+
+pub(crate) const CA: &[u8] = include_bytes!("../../tests/certs/ca.pem");
+const SERVER_CERT: &[u8] = include_bytes!("../../tests/certs/server.pem");
+const SERVER_KEY: &[u8] = include_bytes!("../../tests/certs/server.key");
+
+/// Create a client config, which trusts the CA of the server certificate.
+pub(crate) fn client_config() -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_pem_file(&mut BufReader::new(CA))
+        .unwrap();
+    config.alpn_protocols.push(b"h2".to_vec());
+    config
+}
+
+/// Create the configuration of a session to the server with one attempt, timeouts of two seconds
+/// and POST requests.
+pub(crate) fn session_config() -> SessionConfig {
+    SessionConfig {
+        domain: DOMAIN.to_string(),
+        client_config: Arc::new(client_config()),
+        uri: format!("https://{}/dns-query", DOMAIN),
+        retries: 1,
+        timeout: Duration::from_secs(2),
+        connect_timeout: Duration::from_secs(2),
+        handshake_timeout: Duration::from_secs(2),
+        keepalive: None,
+        post: true,
+    }
+}
+
+fn server_config(client_cert_verifier: Arc<dyn ClientCertVerifier>) -> ServerConfig {
+    let mut config = ServerConfig::new(client_cert_verifier);
+    let cert_chain = certs(&mut BufReader::new(SERVER_CERT)).unwrap();
+    let mut keys = pkcs8_private_keys(&mut BufReader::new(SERVER_KEY)).unwrap();
+    config.set_single_cert(cert_chain, keys.remove(0)).unwrap();
+    config.set_protocols(&[b"h2".to_vec()]);
+    config
+}
+
+fn answer(mut dns: Dns) -> Dns {
+    dns.flags.qr = true;
+    dns.flags.ra = true;
+    for question in &dns.questions {
+        if question.get_qclass() == &QClass::Class(Class::IN)
+            && question.get_qtype() == &QType::Type(Type::A)
+        {
+            let rdata = RData::A(Ipv4Addr::new(192, 0, 2, 1));
+            let domain_name = question.get_domain_name().clone();
+            dns.answers
+                .push(RR::new(domain_name, Class::IN, 300, rdata));
+        }
+    }
+    dns
+}
+
+async fn get_request(request: &mut Request<RecvStream>) -> Option<Bytes> {
+    if request.method() == Method::GET {
+        let query = request.uri().query()?;
+        let dns = query.strip_prefix("dns=")?;
+        decode_config(dns, URL_SAFE_NO_PAD).ok().map(Bytes::from)
+    } else {
+        let body = request.body_mut();
+        let mut bytes = BytesMut::new();
+        while let Some(data) = body.data().await {
+            let data = data.ok()?;
+            body.flow_control().release_capacity(data.len()).ok()?;
+            bytes.extend(data);
+        }
+        Some(bytes.freeze())
+    }
+}
+
+fn get_response(dns: Option<Dns>) -> (Response<()>, Option<Bytes>) {
+    let dns = match dns {
+        Some(dns) => answer(dns),
+        None => {
+            let response = Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(())
+                .unwrap();
+            return (response, None);
+        }
+    };
+    let mut bytes = BytesMut::new();
+    dns.encode(&mut bytes).unwrap();
+    let response = Response::builder()
+        .header("content-type", "application/dns-message")
+        .header("cache-control", "max-age=300")
+        .header("server", "mock")
+        .body(())
+        .unwrap();
+    (response, Some(bytes.freeze()))
+}
+
+async fn stream_handler(mut request: Request<RecvStream>, mut respond: SendResponse<Bytes>) {
+    let dns = match get_request(&mut request).await {
+        Some(msg) => Dns::decode(&msg).ok(),
+        None => None,
+    };
+    match get_response(dns) {
+        (response, Some(bytes)) => {
+            if let Ok(mut send_stream) = respond.send_response(response, false) {
+                let _ = send_stream.send_data(bytes, true);
+            }
+        }
+        (response, None) => {
+            let _ = respond.send_response(response, true);
+        }
+    }
+}
+
+/// The delay of the responses, if the connection is shut down gracefully.
+pub(crate) const GOAWAY_DELAY: Duration = Duration::from_millis(200);
+
+/// Start the server on a random port of the loopback interface.
+pub(crate) async fn start() -> SocketAddr {
+    listen(server_config(NoClientAuth::new()), false).await
+}
+
+/// Start the server on a random port of the loopback interface, which sends a GOAWAY frame after
+/// the first request of a connection. The requests in flight are answered after `GOAWAY_DELAY`.
+pub(crate) async fn start_with_goaway() -> SocketAddr {
+    listen(server_config(NoClientAuth::new()), true).await
+}
+
+/// Start the server on a random port of the loopback interface, which requires a client
+/// certificate.
+pub(crate) async fn start_with_client_auth() -> SocketAddr {
+    let mut root_store = RootCertStore::empty();
+    root_store.add_pem_file(&mut BufReader::new(CA)).unwrap();
+    listen(
+        server_config(AllowAnyAuthenticatedClient::new(root_store)),
+        false,
+    )
+    .await
+}
+
+async fn listen(config: ServerConfig, goaway: bool) -> SocketAddr {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let local_addr = listener.local_addr().unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    spawn(async move {
+        loop {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            spawn(async move {
+                let tls_stream = match acceptor.accept(tcp_stream).await {
+                    Ok(tls_stream) => tls_stream,
+                    Err(_) => return,
+                };
+                let mut connection = match handshake(tls_stream).await {
+                    Ok(connection) => connection,
+                    Err(_) => return,
+                };
+                while let Some(Ok((request, respond))) = connection.accept().await {
+                    if goaway {
+                        connection.graceful_shutdown();
+                        spawn(async move {
+                            delay_for(GOAWAY_DELAY).await;
+                            stream_handler(request, respond).await;
+                        });
+                    } else {
+                        spawn(stream_handler(request, respond));
+                    }
+                }
+            });
+        }
+    });
+    local_addr
+}
@@ -1,19 +1,46 @@
 mod config;
 use config::Config;
+pub(crate) use config::Config as SessionConfig;
 
 mod connection;
 use connection::Connection;
 
+mod backoff;
+use backoff::{Backoff, CircuitBreaker};
+
+mod happy_eyeballs;
+
+mod keepalive;
+use keepalive::Http2Connection;
+
 mod helper;
 #[cfg(feature = "socks5")]
-use helper::socks5_connect;
-use helper::{http2_connect, tcp_connect, try_tls_connect};
+use helper::socks5_tls_connect;
+use helper::{http2_connect, tls_connect};
 
 mod host;
 pub use host::Host;
 
+mod bootstrap;
+pub use bootstrap::Bootstrap;
+
+mod pin;
+use pin::get_tls_error;
+pub use pin::Pin;
+pub(crate) use pin::PinVerifier;
+
 mod session;
 pub(crate) use session::Session;
 
+mod plain;
+pub(crate) use plain::PlainSession;
+
+mod upstream;
+pub(crate) use upstream::{ResponseFuture, Upstream};
+
 mod response;
-use response::response_handler;
+pub(crate) use response::get_ttl;
+use response::{get_min_ttl, get_response, response_handler};
+
+#[cfg(test)]
+pub(crate) mod mock;
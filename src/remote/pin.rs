@@ -0,0 +1,265 @@
+use crate::der::{read_der, SEQUENCE};
+use crate::DohError;
+
+use base64::encode;
+
+use ring::digest::{digest, SHA256, SHA256_OUTPUT_LEN};
+
+use rustls::{
+    Certificate, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError, WebPKIVerifier,
+};
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::Error as IoError;
+
+use webpki::DNSNameRef;
+
+/// The DER tag of the version field of a certificate (`[0] EXPLICIT`).
+const VERSION: u8 = 0xa0;
+/// The prefix of the TLS error, if no pin matches.
+const PIN_MISMATCH: &str = "SPKI pin mismatch: ";
+
+/// The SHA-256 hash of the SubjectPublicKeyInfo of a certificate (see RFC 7469 section 2.4).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pin([u8; SHA256_OUTPUT_LEN]);
+
+impl Pin {
+    /// Create a pin of the SHA-256 hash `hash`. Returns `None` if the hash has not 32 bytes.
+    pub fn new(hash: &[u8]) -> Option<Pin> {
+        if hash.len() != SHA256_OUTPUT_LEN {
+            return None;
+        }
+        let mut pin = [0; SHA256_OUTPUT_LEN];
+        pin.copy_from_slice(hash);
+        Some(Pin(pin))
+    }
+
+    /// Create the pin of the DER encoded certificate `cert`.
+    fn from_cert(cert: &[u8]) -> Option<Pin> {
+        let spki = get_spki(cert)?;
+        Pin::new(digest(&SHA256, spki).as_ref())
+    }
+}
+
+impl Display for Pin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", encode(self.0))
+    }
+}
+
+/// Get the DER encoded SubjectPublicKeyInfo of the DER encoded certificate `cert` (see RFC 5280
+/// section 4.1).
+fn get_spki(cert: &[u8]) -> Option<&[u8]> {
+    let (tag, cert, _) = read_der(cert)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let (tag, mut tbs_certificate, _) = read_der(cert)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    if tbs_certificate.first() == Some(&VERSION) {
+        tbs_certificate = read_der(tbs_certificate)?.2;
+    }
+    // Skip serialNumber, signature, issuer, validity and subject.
+    for _ in 0..5 {
+        tbs_certificate = read_der(tbs_certificate)?.2;
+    }
+    let (tag, _, rest) = read_der(tbs_certificate)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    Some(&tbs_certificate[..tbs_certificate.len() - rest.len()])
+}
+
+/// A certificate verifier, which checks the SPKI pin of the server certificate after the
+/// validation of the chain.
+///
+/// Only the end-entity certificate is pinned. `webpki` does not return the validated path, so the
+/// other certificates, which are sent by the server, may not be part of it, and a server could
+/// append any pinned certificate to its chain. The certificate is accepted if it matches a pin or a
+/// backup pin. A match of a backup pin is logged, because the pins should be updated.
+pub(crate) struct PinVerifier {
+    verifier: WebPKIVerifier,
+    pins: Vec<Pin>,
+    backup_pins: Vec<Pin>,
+}
+
+impl PinVerifier {
+    pub(crate) fn new(pins: Vec<Pin>, backup_pins: Vec<Pin>) -> PinVerifier {
+        PinVerifier {
+            verifier: WebPKIVerifier::new(),
+            pins,
+            backup_pins,
+        }
+    }
+
+    fn verify_pins(&self, presented_certs: &[Certificate]) -> Result<(), TLSError> {
+        let cert = match presented_certs.first() {
+            Some(cert) => cert,
+            None => return Err(TLSError::NoCertificatesPresented),
+        };
+        let pin = match Pin::from_cert(&cert.0) {
+            Some(pin) => pin,
+            None => return Err(TLSError::WebPKIError(webpki::Error::BadDER)),
+        };
+        if self.pins.contains(&pin) {
+            return Ok(());
+        }
+        if self.backup_pins.contains(&pin) {
+            warn!("The certificate matches only the backup pin {}", pin);
+            return Ok(());
+        }
+        Err(TLSError::General(format!("{}{}", PIN_MISMATCH, pin)))
+    }
+}
+
+impl ServerCertVerifier for PinVerifier {
+    fn verify_server_cert(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: DNSNameRef,
+        ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let verified =
+            self.verifier
+                .verify_server_cert(roots, presented_certs, dns_name, ocsp_response)?;
+        self.verify_pins(presented_certs)?;
+        Ok(verified)
+    }
+}
+
+/// Convert the error of a TLS handshake, so that a pin mismatch gets its own variant.
+pub(super) fn get_tls_error(e: IoError) -> DohError {
+    let pins = match e.get_ref().and_then(|e| e.downcast_ref::<TLSError>()) {
+        Some(TLSError::General(msg)) => msg.strip_prefix(PIN_MISMATCH),
+        _ => None,
+    };
+    match pins {
+        Some(pins) => DohError::PinMismatch(pins.split(',').map(|pin| pin.to_string()).collect()),
+        None => DohError::Io(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::decode;
+
+    use rustls::internal::pemfile::certs;
+    use rustls::{Certificate, RootCertStore, ServerCertVerifier, TLSError};
+
+    use std::io::{BufReader, Error as IoError, ErrorKind};
+    use std::sync::Arc;
+
+    use tokio::net::TcpStream;
+    use tokio_rustls::TlsConnector;
+
+    use webpki::DNSNameRef;
+
+    use super::{get_tls_error, Pin, PinVerifier};
+    use crate::remote::mock::{client_config, start, CA, DOMAIN};
+    use crate::{DohError, DohResult};
+
+    const SERVER_CERT: &[u8] = include_bytes!("../../tests/certs/server.pem");
+    /// A second certificate for `localhost`, which is signed by the same CA as `server.pem`.
+    const LEAF_CERT: &[u8] = include_bytes!("../../tests/certs/other.pem");
+    /// The pin of `tests/certs/server.pem`, which was created with `openssl`.
+    const SERVER_PIN: &str = "8ZyAakVmfzINYebqxhGMoVLi1A59H3PfNtshP+Ak5Jg=";
+    /// The pin of `tests/certs/other.pem`.
+    const LEAF_PIN: &str = "kt2ASJq99KRkNM96FnBmZd8Esa+4SXU8u7IHLtiviy0=";
+    const OTHER_PIN: &str = "tD7D2aJ/8nxw4iPvyIhAbV+q0WKf//Fu+cVAEH9xDBU=";
+
+    fn create_pin(pin: &str) -> Pin {
+        Pin::new(&decode(pin).unwrap()).unwrap()
+    }
+
+    fn verify(pins: &[&str], backup_pins: &[&str]) -> Result<(), TLSError> {
+        verify_certs(&[SERVER_CERT], pins, backup_pins)
+    }
+
+    fn verify_certs(
+        pem_certs: &[&[u8]],
+        pins: &[&str],
+        backup_pins: &[&str],
+    ) -> Result<(), TLSError> {
+        let mut roots = RootCertStore::empty();
+        roots.add_pem_file(&mut BufReader::new(CA)).unwrap();
+        let presented_certs: Vec<Certificate> = pem_certs
+            .iter()
+            .flat_map(|pem_cert| certs(&mut BufReader::new(*pem_cert)).unwrap())
+            .collect();
+        let pins = pins.iter().map(|pin| create_pin(pin)).collect();
+        let backup_pins = backup_pins.iter().map(|pin| create_pin(pin)).collect();
+        let verifier = PinVerifier::new(pins, backup_pins);
+        let dns_name = DNSNameRef::try_from_ascii_str(DOMAIN).unwrap();
+        verifier
+            .verify_server_cert(&roots, &presented_certs, dns_name, &[])
+            .map(|_| ())
+    }
+
+    async fn handshake(pin: &str) -> DohResult<()> {
+        let remote_addr = start().await;
+        let mut config = client_config();
+        let verifier = PinVerifier::new(vec![create_pin(pin)], Vec::new());
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(verifier));
+        let tcp_connection = TcpStream::connect(remote_addr).await?;
+        let dns_name = DNSNameRef::try_from_ascii_str(DOMAIN).unwrap();
+        match TlsConnector::from(Arc::new(config))
+            .connect(dns_name, tcp_connection)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(get_tls_error(e)),
+        }
+    }
+
+    #[test]
+    fn test_pin() {
+        let presented_certs = certs(&mut BufReader::new(SERVER_CERT)).unwrap();
+        let pin = Pin::from_cert(&presented_certs[0].0).unwrap();
+        assert_eq!(pin.to_string(), SERVER_PIN);
+        assert!(Pin::new(&[0; 16]).is_none());
+        assert!(Pin::from_cert(&[0x30, 0x03, 0x30, 0x01]).is_none());
+    }
+
+    #[test]
+    fn test_verify() {
+        assert!(verify(&[SERVER_PIN], &[]).is_ok());
+        assert!(verify(&[OTHER_PIN, SERVER_PIN], &[]).is_ok());
+        assert!(verify(&[OTHER_PIN], &[SERVER_PIN]).is_ok());
+
+        let e = verify(&[OTHER_PIN], &[OTHER_PIN]).unwrap_err();
+        match get_tls_error(IoError::new(ErrorKind::InvalidData, e)) {
+            DohError::PinMismatch(pins) => assert_eq!(pins, vec![SERVER_PIN.to_string()]),
+            e => panic!("Unexpected error: {}", e),
+        }
+
+        let e = IoError::new(ErrorKind::InvalidData, TLSError::NoCertificatesPresented);
+        assert!(matches!(get_tls_error(e), DohError::Io(_)));
+    }
+
+    #[test]
+    fn test_verify_end_entity() {
+        assert!(verify_certs(&[LEAF_CERT], &[LEAF_PIN], &[]).is_ok());
+
+        // A pinned certificate, which is appended to the chain of an other server certificate, is
+        // not accepted.
+        let e = verify_certs(&[LEAF_CERT, SERVER_CERT], &[SERVER_PIN], &[SERVER_PIN]).unwrap_err();
+        match get_tls_error(IoError::new(ErrorKind::InvalidData, e)) {
+            DohError::PinMismatch(pins) => assert_eq!(pins, vec![LEAF_PIN.to_string()]),
+            e => panic!("Unexpected error: {}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handshake() {
+        assert!(handshake(SERVER_PIN).await.is_ok());
+        match handshake(OTHER_PIN).await {
+            Err(DohError::PinMismatch(pins)) => assert_eq!(pins, vec![SERVER_PIN.to_string()]),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use bytes::Bytes;
+
+use crate::in_flight::set_id;
+use crate::{DohError, DohResult};
+
+use dns_message_parser::{Dns, Question, MAXIMUM_DNS_PACKET_SIZE};
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use std::cmp::max;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout as create_timeout;
+
+use super::{get_min_ttl, ResponseFuture};
+
+fn get_local_addr(remote_addr: &SocketAddr) -> SocketAddr {
+    if remote_addr.is_ipv4() {
+        SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)
+    } else {
+        SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+    }
+}
+
+fn get_random_id() -> DohResult<u16> {
+    let mut id = [0; 2];
+    if SystemRandom::new().fill(&mut id).is_err() {
+        return Err(DohError::Random);
+    }
+    Ok(u16::from_be_bytes(id))
+}
+
+/// Decode a DNS response and check if it is the answer to the request with the `id` and the
+/// `questions`.
+fn get_dns_response(msg: &[u8], id: u16, questions: &[Question]) -> DohResult<Dns> {
+    let msg = Bytes::copy_from_slice(msg);
+    let dns_response = Dns::decode(&msg)?;
+    if !dns_response.is_response() {
+        return Err(DohError::DnsNotResponse(dns_response));
+    }
+    if dns_response.id != id || dns_response.questions != questions {
+        return Err(DohError::DnsMismatch(dns_response));
+    }
+    Ok(dns_response)
+}
+
+async fn udp_request(
+    remote_addr: SocketAddr,
+    data: &Bytes,
+    id: u16,
+    questions: &[Question],
+) -> DohResult<Dns> {
+    let mut socket = UdpSocket::bind(get_local_addr(&remote_addr)).await?;
+    debug!("Send UDP packet to {}: {:?}", remote_addr, data);
+    socket.send_to(data, &remote_addr).await?;
+
+    let mut buffer = vec![0; MAXIMUM_DNS_PACKET_SIZE];
+    loop {
+        let (n, addr) = socket.recv_from(&mut buffer).await?;
+        if addr != remote_addr {
+            warn!(
+                "Drop UDP packet from {}, expected a response from {}",
+                addr, remote_addr
+            );
+            continue;
+        }
+        match get_dns_response(&buffer[..n], id, questions) {
+            Ok(dns_response) => return Ok(dns_response),
+            Err(e) => warn!("Drop UDP packet from {}: {}", addr, e),
+        }
+    }
+}
+
+async fn tcp_request(
+    remote_addr: SocketAddr,
+    data: &Bytes,
+    id: u16,
+    questions: &[Question],
+) -> DohResult<Dns> {
+    let mut tcp_connection = TcpStream::connect(remote_addr).await?;
+    tcp_connection.set_nodelay(true)?;
+    debug!("Send TCP packet to {}: {:?}", remote_addr, data);
+    tcp_connection
+        .write_all(&(data.len() as u16).to_be_bytes())
+        .await?;
+    tcp_connection.write_all(data).await?;
+
+    let mut length = [0; 2];
+    tcp_connection.read_exact(&mut length).await?;
+    let mut buffer = vec![0; u16::from_be_bytes(length) as usize];
+    tcp_connection.read_exact(&mut buffer).await?;
+    get_dns_response(&buffer, id, questions)
+}
+
+async fn plain_request(
+    remote_addr: SocketAddr,
+    data: &Bytes,
+    id: u16,
+    questions: &[Question],
+) -> DohResult<Dns> {
+    let dns_response = udp_request(remote_addr, data, id, questions).await?;
+    if dns_response.flags.tc {
+        debug!("Response from {} is truncated, retry over TCP", remote_addr);
+        tcp_request(remote_addr, data, id, questions).await
+    } else {
+        Ok(dns_response)
+    }
+}
+
+/// Send the request to the servers in turn, starting with the server `first`, until a valid
+/// response is received.
+///
+/// The `timeout` is split across all attempts, so that the response is received within the
+/// same time as a response from a DoH server.
+async fn plain_requests(
+    remote_addrs: Vec<SocketAddr>,
+    first: usize,
+    retries: u32,
+    timeout: Duration,
+    data: Bytes,
+    id: u16,
+    questions: Vec<Question>,
+) -> DohResult<(Dns, Option<Duration>)> {
+    let attempts = max(retries, 1);
+    let timeout = timeout / attempts;
+    let mut result = Err(DohError::Timeout);
+    for i in 0..attempts as usize {
+        let remote_addr = remote_addrs[(first + i) % remote_addrs.len()];
+        let response = plain_request(remote_addr, &data, id, &questions);
+        result = match create_timeout(timeout, response).await {
+            Ok(Ok(dns_response)) => {
+                let duration = get_min_ttl(&dns_response);
+                return Ok((dns_response, duration));
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(DohError::Timeout),
+        };
+        if let Err(e) = &result {
+            error!("Could not get response from {}: {}", remote_addr, e);
+        }
+    }
+    result
+}
+
+/// A session to classic DNS servers, which do not use HTTPS.
+///
+/// Requests are sent over UDP and retried over TCP if the response is truncated. Every request
+/// starts with the next server, so that a request, which is sent again, tries another server.
+pub(crate) struct PlainSession {
+    remote_addrs: Vec<SocketAddr>,
+    next_remote_addr: usize,
+    retries: u32,
+    timeout: Duration,
+}
+
+impl PlainSession {
+    pub(crate) fn new(
+        remote_addrs: Vec<SocketAddr>,
+        retries: u32,
+        timeout: Duration,
+    ) -> PlainSession {
+        PlainSession {
+            remote_addrs,
+            next_remote_addr: 0,
+            retries,
+            timeout,
+        }
+    }
+
+    pub(crate) fn start_request(
+        &mut self,
+        dns_request: &mut Dns,
+    ) -> DohResult<(ResponseFuture, u32)> {
+        let id = dns_request.id;
+        let random_id = get_random_id()?;
+        dns_request.id = random_id;
+        let result = dns_request.to_bytes();
+        dns_request.id = id;
+        let data = result?;
+        self.start_encoded_request(dns_request, data, random_id)
+    }
+
+    /// Forward the encoded DNS request `msg` of a client with a random ID.
+    ///
+    /// The message is not encoded again, so that the OPT record with the DO bit and the options
+    /// like Client Subnet reaches the server unchanged.
+    pub(crate) fn forward_request(
+        &mut self,
+        dns_request: &Dns,
+        msg: &Bytes,
+    ) -> DohResult<(ResponseFuture, u32)> {
+        let random_id = get_random_id()?;
+        let data = set_id(msg.clone(), random_id);
+        self.start_encoded_request(dns_request, data, random_id)
+    }
+
+    fn start_encoded_request(
+        &mut self,
+        dns_request: &Dns,
+        data: Bytes,
+        random_id: u16,
+    ) -> DohResult<(ResponseFuture, u32)> {
+        if self.remote_addrs.is_empty() {
+            return Err(DohError::CouldNotConnect(Vec::new()));
+        }
+        debug!("Send DNS request to {}: {}", self, dns_request);
+
+        let first = self.next_remote_addr;
+        self.next_remote_addr = (first + 1) % self.remote_addrs.len();
+        let response = plain_requests(
+            self.remote_addrs.clone(),
+            first,
+            self.retries,
+            self.timeout,
+            data,
+            random_id,
+            dns_request.questions.clone(),
+        );
+        Ok((Box::pin(response), 0))
+    }
+}
+
+impl Display for PlainSession {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "udp/tcp {:?}", self.remote_addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use dns_message_parser::{Class, Dns, RData, RR};
+
+    use futures::channel::oneshot::channel;
+
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, UdpSocket};
+    use tokio::spawn;
+
+    use super::PlainSession;
+    use crate::dns::create_test_request;
+    use crate::edns::get_edns;
+
+    fn create_request() -> Dns {
+        let mut dns_request = create_test_request("service.consul");
+        dns_request.id = 1234;
+        dns_request
+    }
+
+    fn create_response(msg: &[u8], ipv4_addr: Ipv4Addr, tc: bool) -> Bytes {
+        let mut dns = Dns::decode(&Bytes::copy_from_slice(msg)).unwrap();
+        dns.flags.qr = true;
+        dns.flags.tc = tc;
+        if !tc {
+            let domain_name = dns.questions[0].get_domain_name().clone();
+            let rdata = RData::A(ipv4_addr);
+            dns.answers.push(RR::new(domain_name, Class::IN, 60, rdata));
+        }
+        dns.to_bytes().unwrap()
+    }
+
+    fn create_session(remote_addr: SocketAddr) -> PlainSession {
+        PlainSession::new(vec![remote_addr], 1, Duration::from_secs(2))
+    }
+
+    async fn answer(mut socket: UdpSocket, ipv4_addr: Ipv4Addr) {
+        let mut buffer = [0; 512];
+        loop {
+            let (n, addr) = socket.recv_from(&mut buffer).await.unwrap();
+            let bytes = create_response(&buffer[..n], ipv4_addr, false);
+            socket.send_to(&bytes, &addr).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_udp_request() {
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let remote_addr = socket.local_addr().unwrap();
+        spawn(async move {
+            let mut buffer = [0; 512];
+            let (n, addr) = socket.recv_from(&mut buffer).await.unwrap();
+            let bytes = create_response(&buffer[..n], Ipv4Addr::new(10, 0, 0, 1), false);
+            socket.send_to(&bytes, &addr).await.unwrap();
+        });
+
+        let mut dns_request = create_request();
+        let mut session = create_session(remote_addr);
+        let (response, _) = session.start_request(&mut dns_request).unwrap();
+        let (dns_response, duration) = response.await.unwrap();
+        assert_eq!(dns_request.id, 1234);
+        assert_eq!(dns_response.answers.len(), 1);
+        assert_eq!(duration, Some(Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    async fn test_forward_request() {
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let remote_addr = socket.local_addr().unwrap();
+        let (sender, receiver) = channel();
+        spawn(async move {
+            let mut buffer = [0; 512];
+            let (n, addr) = socket.recv_from(&mut buffer).await.unwrap();
+            sender.send(get_edns(&buffer[..n])).unwrap();
+            let bytes = create_response(&buffer[..n], Ipv4Addr::new(10, 0, 0, 1), false);
+            socket.send_to(&bytes, &addr).await.unwrap();
+        });
+
+        let dns_request = create_request();
+        let mut msg = dns_request.to_bytes().unwrap().to_vec();
+        msg[11] = 1;
+        // The OPT record with the DO bit and the Client Subnet option with 192.0.2.0/24.
+        msg.extend_from_slice(&[0, 0, 41, 0x10, 0, 0, 0, 0x80, 0, 0, 11]);
+        msg.extend_from_slice(&[0, 8, 0, 7, 0, 1, 24, 0, 192, 0, 2]);
+        let msg = Bytes::from(msg);
+
+        let mut session = create_session(remote_addr);
+        let (response, _) = session.forward_request(&dns_request, &msg).unwrap();
+        let (dns_response, _) = response.await.unwrap();
+        assert_eq!(dns_response.answers.len(), 1);
+        let edns = receiver.await.unwrap().unwrap();
+        assert!(edns.dnssec_ok);
+        assert_eq!(edns.client_subnet, Some(vec![0, 1, 24, 192, 0, 2]));
+    }
+
+    #[tokio::test]
+    async fn test_rotate() {
+        let mut remote_addrs = Vec::new();
+        for i in 1..=2 {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            remote_addrs.push(socket.local_addr().unwrap());
+            spawn(answer(socket, Ipv4Addr::new(10, 0, 0, i)));
+        }
+
+        // Every request starts with the next server, also if the session makes only one attempt.
+        let mut session = PlainSession::new(remote_addrs, 1, Duration::from_secs(2));
+        for i in &[1, 2, 1] {
+            let (response, _) = session.start_request(&mut create_request()).unwrap();
+            let (dns_response, _) = response.await.unwrap();
+            let rdata = dns_response.answers[0].get_rdata();
+            assert_eq!(rdata, &RData::A(Ipv4Addr::new(10, 0, 0, *i)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spoofed_response() {
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut spoof_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let remote_addr = socket.local_addr().unwrap();
+        spawn(async move {
+            let mut buffer = [0; 512];
+            let (n, addr) = socket.recv_from(&mut buffer).await.unwrap();
+            let spoofed = Ipv4Addr::new(6, 6, 6, 6);
+            // A response from another address.
+            let bytes = create_response(&buffer[..n], spoofed, false);
+            spoof_socket.send_to(&bytes, &addr).await.unwrap();
+            // A response with another ID.
+            buffer[0] = !buffer[0];
+            let bytes = create_response(&buffer[..n], spoofed, false);
+            socket.send_to(&bytes, &addr).await.unwrap();
+            buffer[0] = !buffer[0];
+            let bytes = create_response(&buffer[..n], Ipv4Addr::new(10, 0, 0, 1), false);
+            socket.send_to(&bytes, &addr).await.unwrap();
+        });
+
+        let mut session = create_session(remote_addr);
+        let (response, _) = session.start_request(&mut create_request()).unwrap();
+        let (dns_response, _) = response.await.unwrap();
+        let rdata = dns_response.answers[0].get_rdata();
+        assert_eq!(rdata, &RData::A(Ipv4Addr::new(10, 0, 0, 1)));
+    }
+
+    #[tokio::test]
+    async fn test_truncated_response() {
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let remote_addr = socket.local_addr().unwrap();
+        let mut listener = TcpListener::bind(remote_addr).await.unwrap();
+        spawn(async move {
+            let mut buffer = [0; 512];
+            let (n, addr) = socket.recv_from(&mut buffer).await.unwrap();
+            let bytes = create_response(&buffer[..n], Ipv4Addr::UNSPECIFIED, true);
+            socket.send_to(&bytes, &addr).await.unwrap();
+
+            let (mut tcp_connection, _) = listener.accept().await.unwrap();
+            let mut length = [0; 2];
+            tcp_connection.read_exact(&mut length).await.unwrap();
+            let mut buffer = vec![0; u16::from_be_bytes(length) as usize];
+            tcp_connection.read_exact(&mut buffer).await.unwrap();
+            let bytes = create_response(&buffer, Ipv4Addr::new(10, 0, 0, 1), false);
+            let length = (bytes.len() as u16).to_be_bytes();
+            tcp_connection.write_all(&length).await.unwrap();
+            tcp_connection.write_all(&bytes).await.unwrap();
+        });
+
+        let mut session = create_session(remote_addr);
+        let (response, _) = session.start_request(&mut create_request()).unwrap();
+        let (dns_response, _) = response.await.unwrap();
+        assert!(!dns_response.flags.tc);
+        assert_eq!(dns_response.answers.len(), 1);
+    }
+}
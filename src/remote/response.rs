@@ -1,16 +1,17 @@
 use bytes::{Bytes, BytesMut};
 
+use crate::ttl::{is_negative, SERVFAIL_TTL};
 use crate::{DohError, DohResult};
 
-use dns_message_parser::{Dns, MAXIMUM_DNS_PACKET_SIZE};
+use dns_message_parser::{Dns, RCode, RData, MAXIMUM_DNS_PACKET_SIZE, RR};
 
 use h2::client::ResponseFuture;
 use h2::RecvStream;
 
 use http::response::Parts;
 
+use std::cmp::min;
 use std::time::Duration;
-use std::u64::MAX;
 
 fn check_header_status(header: &Parts) -> DohResult<()> {
     if header.status.is_success() {
@@ -49,39 +50,49 @@ fn get_duration(header: &Parts) -> Option<Duration> {
     None
 }
 
-fn get_min_ttl(dns: &Dns) -> Option<Duration> {
-    let mut min_ttl = MAX;
-
-    for answer in &dns.answers {
-        let ttl = *answer.get_ttl() as u64;
-        if ttl < min_ttl {
-            min_ttl = ttl;
-        }
-    }
+/// Get the minimum TTL of the records, where the OPT record is skipped, because its TTL field
+/// contains the extended RCODE and flags.
+fn get_min_ttl_of_rrs(rrs: &[RR]) -> Option<u32> {
+    rrs.iter()
+        .filter(|rr| !matches!(rr.get_rdata(), RData::OPT))
+        .map(|rr| *rr.get_ttl())
+        .min()
+}
 
-    if min_ttl == MAX {
-        for authority in &dns.authorities {
-            let ttl = *authority.get_ttl() as u64;
-            if ttl < min_ttl {
-                min_ttl = ttl;
-            }
-        }
-    }
+/// Get the TTL of a negative response, which is the minimum of the TTL of the SOA record and its
+/// MINIMUM field (see RFC 2308 section 5).
+///
+/// Returns `None` if the response does not contain a SOA record, because then the response should
+/// not be cached.
+fn get_negative_ttl(dns: &Dns) -> Option<u32> {
+    dns.authorities.iter().find_map(|rr| match rr.get_rdata() {
+        RData::SOA(_, _, _, _, _, _, minimum) => Some(min(*rr.get_ttl(), *minimum)),
+        _ => None,
+    })
+}
 
-    if min_ttl == MAX {
-        for additional in &dns.additionals {
-            let ttl = *additional.get_ttl() as u64;
-            if ttl < min_ttl {
-                min_ttl = ttl;
-            }
-        }
-    }
+/// Get the time that the response can be cached, before the TTL limits are applied.
+///
+/// NXDOMAIN and NODATA responses are cached by the SOA record and SERVFAIL responses are cached
+/// only for a short time. Other errors are not cached. A TTL of 0 is returned as it is, so that a
+/// minimum TTL can be applied.
+pub(crate) fn get_ttl(dns: &Dns) -> Option<Duration> {
+    let min_ttl = match dns.flags.rcode {
+        RCode::ServFail => return Some(SERVFAIL_TTL),
+        _ if is_negative(dns) => get_negative_ttl(dns),
+        RCode::NoError => get_min_ttl_of_rrs(&dns.answers),
+        _ => None,
+    };
+
+    min_ttl.map(|min_ttl| Duration::from_secs(min_ttl as u64))
+}
 
-    if min_ttl == MAX || min_ttl == 0 {
-        None
-    } else {
-        Some(Duration::from_secs(min_ttl))
-    }
+/// Get the time that the response can be cached.
+///
+/// Returns `None` for a TTL of 0, because then the response should not be cached (see RFC 1035
+/// section 3.2.1).
+pub(super) fn get_min_ttl(dns: &Dns) -> Option<Duration> {
+    get_ttl(dns).filter(|ttl| *ttl > Duration::from_secs(0))
 }
 
 async fn get_body(recv_stream: &mut RecvStream) -> DohResult<Bytes> {
@@ -116,18 +127,24 @@ async fn get_dns_response(recv_stream: &mut RecvStream) -> DohResult<Dns> {
     Ok(dns_response)
 }
 
-pub(super) async fn response_handler(
-    response_future: ResponseFuture,
-) -> DohResult<(Dns, Option<Duration>)> {
+/// Get the header and the DNS response of the HTTP response.
+pub(super) async fn get_response(response_future: ResponseFuture) -> DohResult<(Parts, Dns)> {
     let response = response_future.await?;
     let (header, mut recv_stream) = response.into_parts();
 
     check_header_status(&header)?;
     check_header_content_type(&header)?;
-    let mut duration = get_duration(&header);
 
     let dns_response = get_dns_response(&mut recv_stream).await?;
+    Ok((header, dns_response))
+}
+
+pub(super) async fn response_handler(
+    response_future: ResponseFuture,
+) -> DohResult<(Dns, Option<Duration>)> {
+    let (header, dns_response) = get_response(response_future).await?;
 
+    let mut duration = get_duration(&header);
     if duration.is_none() {
         duration = get_min_ttl(&dns_response);
     }
@@ -137,9 +154,79 @@ pub(super) async fn response_handler(
 
 #[cfg(test)]
 mod tests {
+    use dns_message_parser::{Class, Dns, DomainName, RCode, RData, RR};
+
     use http::response::Builder;
 
-    use super::check_header_status;
+    use std::convert::TryFrom;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use super::{check_header_status, get_min_ttl, get_ttl};
+    use crate::dns::create_test_request;
+    use crate::ttl::SERVFAIL_TTL;
+
+    fn create_response(rcode: RCode, answers: Vec<RR>, authorities: Vec<RR>) -> Dns {
+        let mut dns = crate::dns::create_response(&create_test_request("example.com"), rcode);
+        dns.answers = answers;
+        dns.authorities = authorities;
+        dns.additionals = vec![RR::new(DomainName::default(), Class::NONE, 0, RData::OPT)];
+        dns
+    }
+
+    fn create_soa(ttl: u32, minimum: u32) -> RR {
+        let domain_name = DomainName::try_from("example.com").unwrap();
+        let m_name = DomainName::try_from("ns.example.com").unwrap();
+        let r_name = DomainName::try_from("hostmaster.example.com").unwrap();
+        let rdata = RData::SOA(m_name, r_name, 1, 7200, 3600, 1209600, minimum);
+        RR::new(domain_name, Class::IN, ttl, rdata)
+    }
+
+    #[test]
+    fn test_get_min_ttl() {
+        let domain_name = DomainName::try_from("example.com").unwrap();
+        let rdata = RData::A(Ipv4Addr::new(192, 0, 2, 1));
+        let answers = vec![
+            RR::new(domain_name.clone(), Class::IN, 300, rdata.clone()),
+            RR::new(domain_name, Class::IN, 60, rdata),
+        ];
+        let dns = create_response(RCode::NoError, answers, vec![create_soa(10, 10)]);
+        assert_eq!(get_min_ttl(&dns), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_get_min_ttl_zero() {
+        let domain_name = DomainName::try_from("example.com").unwrap();
+        let rdata = RData::A(Ipv4Addr::new(192, 0, 2, 1));
+        let answers = vec![RR::new(domain_name, Class::IN, 0, rdata)];
+        let dns = create_response(RCode::NoError, answers, Vec::new());
+        assert_eq!(get_min_ttl(&dns), None);
+        assert_eq!(get_ttl(&dns), Some(Duration::from_secs(0)));
+
+        let dns = create_response(RCode::NXDomain, Vec::new(), vec![create_soa(3600, 0)]);
+        assert_eq!(get_min_ttl(&dns), None);
+    }
+
+    #[test]
+    fn test_get_min_ttl_negative() {
+        let dns = create_response(RCode::NXDomain, Vec::new(), vec![create_soa(3600, 900)]);
+        assert_eq!(get_min_ttl(&dns), Some(Duration::from_secs(900)));
+
+        let dns = create_response(RCode::NoError, Vec::new(), vec![create_soa(300, 900)]);
+        assert_eq!(get_min_ttl(&dns), Some(Duration::from_secs(300)));
+
+        let dns = create_response(RCode::NXDomain, Vec::new(), Vec::new());
+        assert_eq!(get_min_ttl(&dns), None);
+    }
+
+    #[test]
+    fn test_get_min_ttl_error() {
+        let dns = create_response(RCode::ServFail, Vec::new(), Vec::new());
+        assert_eq!(get_min_ttl(&dns), Some(SERVFAIL_TTL));
+
+        let dns = create_response(RCode::Refused, Vec::new(), vec![create_soa(3600, 900)]);
+        assert_eq!(get_min_ttl(&dns), None);
+    }
 
     #[test]
     fn test_check_header_status_200() {
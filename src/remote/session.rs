@@ -2,57 +2,100 @@ use base64::{encode_config, URL_SAFE_NO_PAD};
 
 use bytes::{Bytes, BytesMut};
 
+use crate::in_flight::set_id;
 use crate::{DohError, DohResult};
 
 use dns_message_parser::Dns;
 
+use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
+use h2::client::ResponseFuture;
+
+use http::response::Parts;
 use http::Request;
 
-use super::{response_handler, Config, Connection, Host};
+use tokio::time::timeout;
 
-use rustls::ClientConfig;
+use super::{get_response, response_handler, Bootstrap, CircuitBreaker, Config, Connection, Host};
 
 pub(crate) struct Session {
     config: Config,
     connection: Connection,
     connection_id: u32,
+    bootstrap: Option<Arc<Bootstrap>>,
+    bootstrap_generation: u32,
+    circuit_breaker: CircuitBreaker,
 }
 
 impl Session {
-    pub(crate) fn new(
-        host: Host,
-        domain: String,
-        client_config: Arc<ClientConfig>,
-        uri: String,
-        retries: u32,
-        post: bool,
-    ) -> Session {
-        let config = Config::new(domain, client_config, uri, retries, post);
+    pub(crate) fn new(host: Host, config: Config, bootstrap: Option<Bootstrap>) -> Session {
         let connection = host.into_connection();
         Session {
             config,
             connection,
             connection_id: 0,
+            bootstrap: bootstrap.map(Arc::new),
+            bootstrap_generation: 0,
+            circuit_breaker: CircuitBreaker::new(),
+        }
+    }
+
+    /// Use the addresses of the bootstrap resolver, if they were resolved again.
+    fn update_remote_addrs(&mut self) {
+        if let Some(bootstrap) = &self.bootstrap {
+            if let Some(remote_addrs) = bootstrap.get_remote_addrs(&mut self.bootstrap_generation) {
+                self.connection.set_remote_addrs(remote_addrs);
+            }
         }
     }
 
-    async fn connect(&mut self) -> DohResult<()> {
+    /// Get a future, which resolves the hostname with the bootstrap resolver if its addresses are
+    /// expired. The addresses are used by the next `connect`.
+    ///
+    /// The future does not borrow the session, so that the session does not have to be locked
+    /// while the hostname is resolved.
+    pub(crate) fn resolve(&self) -> impl Future<Output = ()> + Send + 'static {
+        let bootstrap = self.bootstrap.clone();
+        let retries = self.config.retries;
+        let timeout = self.config.timeout;
+        async move {
+            if let Some(bootstrap) = bootstrap {
+                bootstrap.refresh(retries, timeout).await;
+            }
+        }
+    }
+
+    pub(crate) async fn connect(&mut self) -> DohResult<()> {
         if self.connection.is_connected() {
             Ok(())
+        } else if self.circuit_breaker.is_open() {
+            Err(DohError::CircuitOpen)
         } else {
-            let config = &self.config;
-            let client_config = &config.client_config;
-            let domain = &config.domain.as_str();
-            for i in 0..config.retries {
+            // If the circuit is half-open then only one attempt is made.
+            let retries = if self.circuit_breaker.is_half_open() {
+                1
+            } else {
+                self.config.retries
+            };
+            // The attempts are not delayed, because the upstream is locked during the connect. The
+            // backoff is applied by the circuit breaker, which lets requests fail fast instead.
+            for i in 0..retries {
+                self.update_remote_addrs();
+                let config = &self.config;
+                let domain = &config.domain;
                 info!("Try to connect to {}: {}", self.connection, i + 1);
-                match self.connection.connect(client_config, domain).await {
+                match self.connection.connect(config).await {
                     Ok(_) => {
                         info!("Connected to {} via {}", domain, self.connection);
                         self.connection_id += 1;
+                        self.circuit_breaker.success();
+                        if let Some(bootstrap) = &self.bootstrap {
+                            bootstrap.connected();
+                        }
                         return Ok(());
                     }
                     Err(e) => {
@@ -60,14 +103,78 @@ impl Session {
                             "Could not connect to {} via {}: {}",
                             domain, self.connection, e
                         );
+                        if let DohError::CouldNotConnect(_) = e {
+                            if let Some(bootstrap) = &self.bootstrap {
+                                bootstrap.could_not_connect();
+                            }
+                        }
                     }
                 }
             }
+            let delay = self.circuit_breaker.failure();
+            error!(
+                "Could not connect to {}, the circuit breaker is open for {:?}",
+                self.connection, delay
+            );
             let remote_addrs = self.connection.get_remote_addrs();
             Err(DohError::CouldNotConnect(remote_addrs))
         }
     }
 
+    /// Connect again if the connection was closed, so that the next request does not have to
+    /// wait for the handshake.
+    pub(crate) async fn reconnect(&mut self) -> DohResult<()> {
+        if self.connection_id == 0 || self.connection.is_connected() {
+            // Not connected yet, the first request establishes the connection.
+            Ok(())
+        } else {
+            info!("Connection to {} was closed, reconnect", self.connection);
+            self.connect().await
+        }
+    }
+
+    /// Close the current connection and connect again, even if the connection is still open.
+    pub(crate) async fn force_reconnect(&mut self) -> DohResult<()> {
+        self.disconnect(self.connection_id);
+        self.connect().await
+    }
+
+    /// Use the addresses `remote_addrs` for the next connection and close the current one.
+    ///
+    /// If a bootstrap resolver is used, then the addresses are replaced after their TTL is
+    /// expired.
+    pub(crate) fn switch(&mut self, remote_addrs: Vec<SocketAddr>) {
+        info!("Switch to {:?}", remote_addrs);
+        self.connection.set_remote_addrs(remote_addrs);
+        self.connection.disconnect();
+    }
+
+    /// Resolve the hostname with the bootstrap resolver again, even if the addresses are not
+    /// expired. The current connection is kept, the addresses are used for the next one.
+    ///
+    /// Returns `None` if no bootstrap resolver is used. Like `resolve`, the future does not borrow
+    /// the session.
+    pub(crate) fn resolve_again(
+        &self,
+    ) -> Option<impl Future<Output = DohResult<Vec<SocketAddr>>> + Send + 'static> {
+        let bootstrap = self.bootstrap.clone()?;
+        let retries = self.config.retries;
+        let timeout = self.config.timeout;
+        Some(async move { bootstrap.resolve(retries, timeout).await })
+    }
+
+    pub(crate) fn timeout(&self) -> Duration {
+        self.config.timeout
+    }
+
+    pub(crate) fn connection_id(&self) -> u32 {
+        self.connection_id
+    }
+
+    pub(crate) fn is_connected(&mut self) -> bool {
+        self.connection.is_connected()
+    }
+
     pub(crate) fn disconnect(&mut self, connection_id: u32) {
         if self.connection_id == connection_id {
             debug!("Disconnect connetion to server");
@@ -75,13 +182,7 @@ impl Session {
         }
     }
 
-    pub(crate) async fn send_request(
-        &mut self,
-        data: Bytes,
-    ) -> DohResult<(
-        impl Future<Output = DohResult<(Dns, Option<Duration>)>>,
-        u32,
-    )> {
+    async fn send_http_request(&mut self, data: Bytes) -> DohResult<ResponseFuture> {
         let config = &self.config;
         let post = config.post;
 
@@ -109,30 +210,74 @@ impl Session {
         };
 
         debug!("Send HTTP2 request to server: {:?}", request);
-        let (response, mut request) = self.connection.send_request(request).await?;
+        // A GET request has no body, so the stream is closed with the headers.
+        let (response, mut request) = self.connection.send_request(request, !post).await?;
         if post {
             debug!("Send HTTP2 body: {:?}", data);
             request.send_data(data, true)?;
         }
-        Ok((response_handler(response), self.connection_id))
+        Ok(response)
     }
 
-    pub(crate) async fn start_request(
+    pub(crate) async fn send_request(
         &mut self,
-        dns_request: &mut Dns,
+        data: Bytes,
     ) -> DohResult<(
         impl Future<Output = DohResult<(Dns, Option<Duration>)>>,
         u32,
     )> {
-        self.connect().await?;
+        let response = self.send_http_request(data).await?;
+        Ok((response_handler(response), self.connection_id))
+    }
+
+    /// Encode the DNS request with the ID 0, which is recommended for caching (see RFC 8484
+    /// section 4.1).
+    fn encode_request(dns_request: &mut Dns) -> DohResult<Bytes> {
         let mut bytes = BytesMut::new();
         let id = dns_request.id;
         dns_request.id = 0;
-        dns_request.encode(&mut bytes)?;
+        let result = dns_request.encode(&mut bytes);
         debug!("Send DNS request to server: {}", dns_request);
-        let data = bytes.freeze();
         dns_request.id = id;
+        result?;
+        Ok(bytes.freeze())
+    }
+
+    pub(crate) async fn start_request(
+        &mut self,
+        dns_request: &mut Dns,
+    ) -> DohResult<(
+        impl Future<Output = DohResult<(Dns, Option<Duration>)>>,
+        u32,
+    )> {
+        let data = Session::encode_request(dns_request)?;
+        self.start_encoded_request(data).await
+    }
 
+    /// Forward the encoded DNS request `msg` of a client with the ID 0.
+    ///
+    /// The message is not encoded again, so that the OPT record with the DO bit and the options
+    /// like Client Subnet reaches the server unchanged.
+    pub(crate) async fn forward_request(
+        &mut self,
+        dns_request: &Dns,
+        msg: &Bytes,
+    ) -> DohResult<(
+        impl Future<Output = DohResult<(Dns, Option<Duration>)>>,
+        u32,
+    )> {
+        debug!("Send DNS request to server: {}", dns_request);
+        self.start_encoded_request(set_id(msg.clone(), 0)).await
+    }
+
+    async fn start_encoded_request(
+        &mut self,
+        data: Bytes,
+    ) -> DohResult<(
+        impl Future<Output = DohResult<(Dns, Option<Duration>)>>,
+        u32,
+    )> {
+        self.connect().await?;
         match self.send_request(data).await {
             Ok(r) => Ok(r),
             Err(e) => {
@@ -141,4 +286,181 @@ impl Session {
             }
         }
     }
+
+    /// Send a single DNS request and get the header of the HTTP response and the DNS response.
+    ///
+    /// The connection has to be established by `connect` before.
+    pub(crate) async fn query(&mut self, dns_request: &mut Dns) -> DohResult<(Parts, Dns)> {
+        let data = Session::encode_request(dns_request)?;
+        let response = self.send_http_request(data).await?;
+        timeout(self.config.timeout, get_response(response)).await?
+    }
+}
+
+impl Display for Session {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{} via {}", self.config.uri, self.connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use dns_message_parser::{Class, Dns, QType, RData, Type, RR};
+
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::{Duration, Instant};
+
+    use tokio::net::{TcpListener, UdpSocket};
+    use tokio::spawn;
+    use tokio::time::delay_for;
+
+    use super::Session;
+    use crate::dns::create_test_request;
+    use crate::remote::mock::{session_config, start, start_with_goaway, DOMAIN, GOAWAY_DELAY};
+    use crate::remote::{Bootstrap, Host, SessionConfig};
+    use crate::DohError;
+
+    fn create_request() -> Dns {
+        let mut dns_request = create_test_request("example.com");
+        dns_request.id = 0x1234;
+        dns_request
+    }
+
+    fn create_session(remote_addr: SocketAddr, retries: u32, post: bool) -> Session {
+        create_session_with_bootstrap(vec![remote_addr], retries, post, None)
+    }
+
+    fn create_session_with_bootstrap(
+        remote_addrs: Vec<SocketAddr>,
+        retries: u32,
+        post: bool,
+        bootstrap: Option<Bootstrap>,
+    ) -> Session {
+        let config = SessionConfig {
+            retries,
+            post,
+            ..session_config()
+        };
+        Session::new(Host::Direct(remote_addrs), config, bootstrap)
+    }
+
+    /// Start a plain DNS server, which answers the first A and AAAA request with `127.0.0.1` and
+    /// does not respond after that.
+    async fn start_bootstrap_server() -> SocketAddr {
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let bootstrap_addr = socket.local_addr().unwrap();
+        spawn(async move {
+            let mut buffer = [0; 512];
+            for i in 0.. {
+                let (n, addr) = socket.recv_from(&mut buffer).await.unwrap();
+                if i >= 2 {
+                    continue;
+                }
+                let mut dns = Dns::decode(&Bytes::copy_from_slice(&buffer[..n])).unwrap();
+                dns.flags.qr = true;
+                if dns.questions[0].get_qtype() == &QType::Type(Type::A) {
+                    let domain_name = dns.questions[0].get_domain_name().clone();
+                    let rdata = RData::A(Ipv4Addr::LOCALHOST);
+                    dns.answers.push(RR::new(domain_name, Class::IN, 60, rdata));
+                }
+                let bytes = dns.to_bytes().unwrap();
+                socket.send_to(&bytes, &addr).await.unwrap();
+            }
+        });
+        bootstrap_addr
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        for post in &[true, false] {
+            let mut session = create_session(start().await, 1, *post);
+            let mut dns_request = create_request();
+            session.connect().await.unwrap();
+            assert_eq!(session.connection_id(), 1);
+
+            let (header, dns_response) = session.query(&mut dns_request).await.unwrap();
+            assert!(header.status.is_success());
+            assert_eq!(header.headers.get("server").unwrap(), "mock");
+            assert_eq!(dns_response.questions, dns_request.questions);
+            assert_eq!(dns_response.answers.len(), 1);
+            assert_eq!(dns_request.id, 0x1234);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_goaway() {
+        let mut session = create_session(start_with_goaway().await, 1, true);
+        let (response, connection_id) = session.start_request(&mut create_request()).await.unwrap();
+        assert_eq!(connection_id, 1);
+
+        // After the GOAWAY, the request is still in flight, but new requests use a fresh
+        // connection.
+        delay_for(GOAWAY_DELAY / 2).await;
+        assert!(!session.is_connected());
+        let (next_response, connection_id) =
+            session.start_request(&mut create_request()).await.unwrap();
+        assert_eq!(connection_id, 2);
+
+        let (dns_response, _) = response.await.unwrap();
+        assert_eq!(dns_response.answers.len(), 1);
+        let (dns_response, _) = next_response.await.unwrap();
+        assert_eq!(dns_response.answers.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_unreachable() {
+        let remote_addr = start().await;
+        let bootstrap_addr = start_bootstrap_server().await;
+        let bootstrap = Bootstrap::new(DOMAIN, remote_addr.port(), vec![bootstrap_addr]).unwrap();
+        let mut session = create_session_with_bootstrap(Vec::new(), 1, true, Some(bootstrap));
+
+        // The hostname was never resolved, so the first request waits for the resolution.
+        session.resolve().await;
+        let (response, _) = session.start_request(&mut create_request()).await.unwrap();
+        assert_eq!(response.await.unwrap().0.answers.len(), 1);
+
+        // The addresses are expired and the bootstrap server does not respond anymore, but the
+        // requests do not wait for the resolution and use the last addresses.
+        let bootstrap = session.bootstrap.clone().unwrap();
+        bootstrap.could_not_connect();
+        bootstrap.could_not_connect();
+        assert!(bootstrap.is_expired());
+        for _ in 0..3 {
+            let start = Instant::now();
+            session.resolve().await;
+            session.disconnect(session.connection_id());
+            let (response, _) = session.start_request(&mut create_request()).await.unwrap();
+            assert_eq!(response.await.unwrap().0.answers.len(), 1);
+            assert!(start.elapsed() < Duration::from_millis(500));
+        }
+        assert!(bootstrap.is_expired());
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        // Bind and close a listener to get a port, which refuses connections.
+        let remote_addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut session = create_session(remote_addr, 3, true);
+        // The attempts are not delayed, because the session is locked during the connect.
+        let start = Instant::now();
+        match session.connect().await {
+            Err(DohError::CouldNotConnect(_)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        // The next request fails fast, until the backoff delay of the circuit breaker is over.
+        let start = Instant::now();
+        match session.connect().await {
+            Err(DohError::CircuitOpen) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
 }
@@ -0,0 +1,74 @@
+use bytes::Bytes;
+
+use crate::DohResult;
+
+use dns_message_parser::Dns;
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use super::{PlainSession, Session};
+
+pub(crate) type ResponseFuture =
+    Pin<Box<dyn Future<Output = DohResult<(Dns, Option<Duration>)>> + Send>>;
+
+/// A server, which DNS requests can be forwarded to.
+pub(crate) enum Upstream {
+    Doh(Box<Session>),
+    Plain(PlainSession),
+}
+
+impl Upstream {
+    /// Forward the encoded DNS request `msg` of a client, which is decoded as `dns_request`.
+    pub(crate) async fn forward_request(
+        &mut self,
+        dns_request: &Dns,
+        msg: &Bytes,
+    ) -> DohResult<(ResponseFuture, u32)> {
+        match self {
+            Upstream::Doh(session) => {
+                let (response, connection_id) = session.forward_request(dns_request, msg).await?;
+                Ok((Box::pin(response), connection_id))
+            }
+            Upstream::Plain(session) => session.forward_request(dns_request, msg),
+        }
+    }
+
+    /// Get a future, which resolves the hostname of the DoH server with the bootstrap resolver if
+    /// its addresses are expired (see `Session::resolve`).
+    pub(crate) fn resolve(&self) -> impl Future<Output = ()> + Send + 'static {
+        let resolve = match self {
+            Upstream::Doh(session) => Some(session.resolve()),
+            Upstream::Plain(_) => None,
+        };
+        async move {
+            if let Some(resolve) = resolve {
+                resolve.await;
+            }
+        }
+    }
+
+    pub(crate) async fn reconnect(&mut self) -> DohResult<()> {
+        match self {
+            Upstream::Doh(session) => session.reconnect().await,
+            Upstream::Plain(_) => Ok(()),
+        }
+    }
+
+    pub(crate) fn disconnect(&mut self, connection_id: u32) {
+        if let Upstream::Doh(session) = self {
+            session.disconnect(connection_id);
+        }
+    }
+}
+
+impl Display for Upstream {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Upstream::Doh(session) => write!(f, "{}", session),
+            Upstream::Plain(session) => write!(f, "{}", session),
+        }
+    }
+}
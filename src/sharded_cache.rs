@@ -0,0 +1,212 @@
+use crate::cache::Stats;
+use crate::Cache;
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// The number of shards of the cache.
+const SHARDS: usize = 16;
+
+/// Get the part `i` of `total`, if it is split into `n` parts, which differ at most by one.
+fn split(total: usize, n: usize, i: usize) -> usize {
+    total / n + if i < total % n { 1 } else { 0 }
+}
+
+/// A cache, which is split into shards by the hash of the key.
+///
+/// Every shard has its own lock, so that lookups of keys in different shards do not wait for each
+/// other. There is no shared read lock, because every lookup updates the order of the least
+/// recently used entries and the hit counters of its shard. A lookup holds the lock only for a hash
+/// lookup and the relinking of the entry, without an allocation or an `.await`, so the threads
+/// rarely wait for each other (see `bench_sharded_cache`).
+///
+/// The limits are split across the shards, therefore the least recently used entry of a shard is
+/// evicted and not the least recently used entry of the whole cache.
+pub(crate) struct ShardedCache<K: Eq + Hash, V> {
+    shards: Vec<Mutex<Cache<K, V>>>,
+    hash_builder: RandomState,
+}
+
+impl<K: Eq + Hash + Clone, V> ShardedCache<K, V> {
+    /// Create a new cache with `max_size` entries, which use at most `max_bytes` bytes, if it is
+    /// set (see `Cache::new`).
+    pub(crate) fn new(max_size: usize, max_bytes: Option<usize>, prefetch: u32) -> Self {
+        ShardedCache::with_shards(max_size, max_bytes, prefetch, SHARDS)
+    }
+
+    fn with_shards(max_size: usize, max_bytes: Option<usize>, prefetch: u32, n: usize) -> Self {
+        // Every shard has to hold at least one entry.
+        let n = n.min(max_size).max(1);
+        let shards = (0..n)
+            .map(|i| {
+                let max_size = split(max_size, n, i);
+                let max_bytes = max_bytes.map(|max_bytes| split(max_bytes, n, i));
+                Mutex::new(Cache::new(max_size, max_bytes, prefetch))
+            })
+            .collect();
+        ShardedCache {
+            shards,
+            hash_builder: RandomState::new(),
+        }
+    }
+
+    /// Lock the shard of the key `k`.
+    ///
+    /// The guard must not be held across an `.await`, because the lock blocks the thread.
+    pub(crate) fn lock(&self, k: &K) -> MutexGuard<'_, Cache<K, V>> {
+        let shard = self.hash_builder.hash_one(k) as usize % self.shards.len();
+        lock(&self.shards[shard])
+    }
+
+    /// Call `f` for every shard, one after the other.
+    pub(crate) fn for_each<F>(&self, mut f: F)
+    where
+        F: FnMut(&Cache<K, V>),
+    {
+        for shard in &self.shards {
+            f(&lock(shard));
+        }
+    }
+
+    /// Get the number of entries.
+    pub(crate) fn len(&self) -> usize {
+        let mut len = 0;
+        self.for_each(|cache| len += cache.len());
+        len
+    }
+
+    /// Get the estimated memory usage of all entries in bytes.
+    pub(crate) fn bytes(&self) -> usize {
+        let mut bytes = 0;
+        self.for_each(|cache| bytes += cache.bytes());
+        bytes
+    }
+
+    pub(crate) fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        self.for_each(|cache| stats.add(&cache.stats()));
+        stats
+    }
+
+    /// Remove all entries, whose key matches `f`.
+    ///
+    /// Returns the number of removed entries.
+    pub(crate) fn remove_if<F>(&self, f: F) -> usize
+    where
+        F: Fn(&K) -> bool,
+    {
+        self.shards
+            .iter()
+            .map(|shard| lock(shard).remove_if(&f))
+            .sum()
+    }
+
+    /// Remove all entries.
+    ///
+    /// Returns the number of removed entries.
+    pub(crate) fn clear(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let mut guard_cache = lock(shard);
+                let len = guard_cache.len();
+                guard_cache.clear();
+                len
+            })
+            .sum()
+    }
+}
+
+/// Lock the shard, even if another thread panicked while it held the lock, because the cache is
+/// still consistent in this case.
+fn lock<K: Eq + Hash, V>(shard: &Mutex<Cache<K, V>>) -> MutexGuard<'_, Cache<K, V>> {
+    shard.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread::spawn;
+    use std::time::{Duration, Instant};
+
+    use super::{split, ShardedCache, SHARDS};
+
+    #[test]
+    fn test_split() {
+        let parts: Vec<usize> = (0..4).map(|i| split(10, 4, i)).collect();
+        assert_eq!(parts, vec![3, 3, 2, 2]);
+        assert_eq!(parts.iter().sum::<usize>(), 10);
+    }
+
+    #[test]
+    fn test_sharded_cache() {
+        // The keys are not distributed evenly across the shards, so the cache is larger.
+        let cache: ShardedCache<u32, u32> = ShardedCache::new(1024, None, 0);
+        assert_eq!(cache.shards.len(), SHARDS);
+        let d = Duration::from_secs(10);
+
+        for k in 0..64 {
+            cache.lock(&k).put(k, k + 1, 0, d);
+        }
+        for k in 0..64 {
+            assert_eq!(cache.lock(&k).get(&k), Some(&mut (k + 1)));
+        }
+        assert_eq!(cache.len(), 64);
+
+        let cache: ShardedCache<u32, u32> = ShardedCache::new(2, None, 0);
+        assert_eq!(cache.shards.len(), 2);
+    }
+
+    /// Measure the lookups per second of a single lock and of the shards with a different number
+    /// of threads.
+    ///
+    /// Run it with `cargo test --release -- --ignored bench_sharded_cache --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_sharded_cache() {
+        const KEYS: u64 = 16384;
+        const LOOKUPS: u64 = 1_000_000;
+
+        for shards in &[1, SHARDS] {
+            for threads in &[1, 2, 4, 8] {
+                let cache = ShardedCache::with_shards(2 * KEYS as usize, None, 0, *shards);
+                let d = Duration::from_secs(3600);
+                for k in 0..KEYS {
+                    cache.lock(&k).put(k, k, 64, d);
+                }
+                let cache = Arc::new(cache);
+
+                let start = Instant::now();
+                let handles: Vec<_> = (0..*threads)
+                    .map(|thread| {
+                        let cache = cache.clone();
+                        spawn(move || {
+                            let mut k = thread + 1;
+                            for _ in 0..LOOKUPS {
+                                // A simple pseudo random number generator (xorshift).
+                                k ^= k << 13;
+                                k ^= k >> 7;
+                                k ^= k << 17;
+                                let key = k % KEYS;
+                                assert!(cache.lock(&key).get(&key).is_some());
+                            }
+                        })
+                    })
+                    .collect();
+                for handle in handles {
+                    handle.join().unwrap();
+                }
+                let elapsed = start.elapsed().as_secs_f64();
+
+                let lookups = (LOOKUPS * threads) as f64;
+                println!(
+                    "{:2} shards, {} threads: {:.2} million lookups per second",
+                    shards,
+                    threads,
+                    lookups / elapsed / 1_000_000.0
+                );
+            }
+        }
+    }
+}
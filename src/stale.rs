@@ -0,0 +1,68 @@
+use crate::ttl::copy_with_ttl;
+
+use dns_message_parser::{Dns, RData};
+
+use std::time::Duration;
+
+/// The TTL of stale records in a response (see RFC 8767 section 4).
+const STALE_TTL: u32 = 30;
+
+/// The configuration of serve-stale (see RFC 8767).
+pub(crate) struct ServeStale {
+    /// The maximum time after the expiry, during that a cache entry may be served.
+    pub(crate) max_age: Duration,
+    /// The time after that stale data is answered, if the server has not responded yet.
+    pub(crate) timeout: Duration,
+}
+
+impl ServeStale {
+    pub(crate) fn new(max_age: Duration, timeout: Duration) -> ServeStale {
+        ServeStale { max_age, timeout }
+    }
+}
+
+/// Create a response from an expired cache entry, where all TTLs are set to 30 seconds.
+///
+/// The OPT record is not copied, because it belongs to the original response.
+pub(crate) fn create_stale_response(dns: &Dns, id: u16) -> Dns {
+    let mut stale_response = copy_with_ttl(dns, |_| STALE_TTL);
+    stale_response.id = id;
+    stale_response.flags.aa = false;
+    stale_response.flags.ad = false;
+    stale_response
+        .additionals
+        .retain(|rr| !matches!(rr.get_rdata(), RData::OPT));
+    stale_response
+}
+
+#[cfg(test)]
+mod tests {
+    use dns_message_parser::{Class, DomainName, RCode, RData, RR};
+
+    use std::net::Ipv4Addr;
+
+    use super::{create_stale_response, STALE_TTL};
+    use crate::dns::{create_response, create_test_request};
+
+    #[test]
+    fn test_create_stale_response() {
+        let dns_request = create_test_request("example.com");
+        let domain_name = dns_request.questions[0].get_domain_name().clone();
+        let rdata = RData::A(Ipv4Addr::new(192, 0, 2, 1));
+        let mut dns = create_response(&dns_request, RCode::NoError);
+        dns.flags.aa = true;
+        dns.flags.ad = true;
+        dns.answers = vec![RR::new(domain_name, Class::IN, 3600, rdata.clone())];
+        dns.additionals = vec![RR::new(DomainName::default(), Class::NONE, 0, RData::OPT)];
+
+        let stale_response = create_stale_response(&dns, 1234);
+        assert_eq!(stale_response.id, 1234);
+        assert!(!stale_response.flags.aa);
+        assert!(!stale_response.flags.ad);
+        assert_eq!(stale_response.questions, dns.questions);
+        assert_eq!(stale_response.answers.len(), 1);
+        assert_eq!(stale_response.answers[0].get_ttl(), &STALE_TTL);
+        assert_eq!(stale_response.answers[0].get_rdata(), &rdata);
+        assert!(stale_response.additionals.is_empty());
+    }
+}
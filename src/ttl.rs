@@ -0,0 +1,221 @@
+use dns_message_parser::{Dns, Flags, RCode, RData, RR};
+
+use std::cmp::{max, min};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// The time that SERVFAIL responses are cached (see RFC 2308 section 7.1).
+pub(crate) const SERVFAIL_TTL: Duration = Duration::from_secs(5);
+
+/// Check if the response is a NXDOMAIN or a NODATA response (see RFC 2308 section 2).
+pub(crate) fn is_negative(dns: &Dns) -> bool {
+    match dns.flags.rcode {
+        RCode::NXDomain => true,
+        RCode::NoError => dns.answers.is_empty(),
+        _ => false,
+    }
+}
+
+/// The limits of the time that responses are cached.
+pub(crate) struct TtlLimits {
+    /// The minimum time that responses are cached.
+    min: Duration,
+    /// The maximum time that responses are cached, if it is set.
+    max: Option<Duration>,
+    /// The maximum time that negative responses are cached (see RFC 2308 section 5).
+    negative_max: Duration,
+    /// Whether the TTLs in the responses to the clients are rewritten to the limits.
+    rewrite: bool,
+}
+
+impl TtlLimits {
+    pub(crate) fn new(
+        min: Duration,
+        max: Option<Duration>,
+        negative_max: Duration,
+        rewrite: bool,
+    ) -> TtlLimits {
+        TtlLimits {
+            min,
+            max,
+            negative_max,
+            rewrite,
+        }
+    }
+
+    fn clamp(&self, duration: Duration) -> Duration {
+        let duration = max(duration, self.min);
+        match self.max {
+            Some(max) => min(duration, max),
+            None => duration,
+        }
+    }
+
+    /// Limit the time `duration` that the response `dns` is cached.
+    ///
+    /// SERVFAIL responses are not affected by the minimum.
+    pub(crate) fn limit(&self, dns: &Dns, duration: Duration) -> Duration {
+        if dns.flags.rcode == RCode::ServFail {
+            min(duration, SERVFAIL_TTL)
+        } else if is_negative(dns) {
+            min(self.clamp(duration), self.negative_max)
+        } else {
+            self.clamp(duration)
+        }
+    }
+
+    /// Rewrite the TTLs of the records in the response `dns` to the minimum and the maximum, if
+    /// it is enabled.
+    pub(crate) fn rewrite(&self, dns: Dns) -> Dns {
+        if !self.rewrite || dns.flags.rcode == RCode::ServFail {
+            return dns;
+        }
+        copy_with_ttl(&dns, |ttl| {
+            let ttl = self.clamp(Duration::from_secs(ttl as u64)).as_secs();
+            u32::try_from(ttl).unwrap_or(u32::MAX)
+        })
+    }
+}
+
+fn copy_rrs<F>(rrs: &[RR], f: &F) -> Vec<RR>
+where
+    F: Fn(u32) -> u32,
+{
+    rrs.iter()
+        .map(|rr| {
+            let domain_name = rr.get_domain_name().clone();
+            let class = rr.get_class().clone();
+            let rdata = rr.get_rdata().clone();
+            // The TTL field of an OPT record contains the extended RCODE and flags.
+            let ttl = match rdata {
+                RData::OPT => *rr.get_ttl(),
+                _ => f(*rr.get_ttl()),
+            };
+            RR::new(domain_name, class, ttl, rdata)
+        })
+        .collect()
+}
+
+/// Copy the DNS message, where the TTL of every record is replaced by `f(ttl)`.
+pub(crate) fn copy_with_ttl<F>(dns: &Dns, f: F) -> Dns
+where
+    F: Fn(u32) -> u32,
+{
+    let flags = &dns.flags;
+    Dns {
+        id: dns.id,
+        flags: Flags {
+            qr: flags.qr,
+            opcode: flags.opcode.clone(),
+            aa: flags.aa,
+            tc: flags.tc,
+            rd: flags.rd,
+            ra: flags.ra,
+            ad: flags.ad,
+            cd: flags.cd,
+            rcode: flags.rcode.clone(),
+        },
+        questions: dns.questions.clone(),
+        answers: copy_rrs(&dns.answers, &f),
+        authorities: copy_rrs(&dns.authorities, &f),
+        additionals: copy_rrs(&dns.additionals, &f),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dns_message_parser::{Class, Dns, DomainName, RCode, RData, RR};
+
+    use std::convert::TryFrom;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use super::{copy_with_ttl, is_negative, TtlLimits, SERVFAIL_TTL};
+    use crate::dns::create_test_request;
+
+    fn create_ttl_limits(rewrite: bool) -> TtlLimits {
+        let min = Duration::from_secs(60);
+        let max = Some(Duration::from_secs(3600));
+        TtlLimits::new(min, max, Duration::from_secs(900), rewrite)
+    }
+
+    fn create_response(rcode: RCode, answers: Vec<RR>) -> Dns {
+        let mut dns = crate::dns::create_response(&create_test_request("example.com"), rcode);
+        dns.id = 1234;
+        dns.answers = answers;
+        dns
+    }
+
+    #[test]
+    fn test_copy_with_ttl() {
+        let domain_name = DomainName::try_from("example.com").unwrap();
+        let rdata = RData::A(Ipv4Addr::new(192, 0, 2, 1));
+        let answer = RR::new(domain_name.clone(), Class::IN, 300, rdata.clone());
+        let mut dns = create_response(RCode::NoError, vec![answer]);
+        dns.flags.ad = true;
+        dns.authorities = vec![RR::new(domain_name, Class::IN, 20, rdata)];
+        dns.additionals = vec![RR::new(DomainName::default(), Class::NONE, 0, RData::OPT)];
+
+        let copy = copy_with_ttl(&dns, |ttl| ttl.saturating_sub(60));
+        assert_eq!(copy.id, dns.id);
+        assert_eq!(copy.flags, dns.flags);
+        assert_eq!(copy.questions, dns.questions);
+        assert_eq!(copy.answers[0].get_ttl(), &240);
+        assert_eq!(copy.answers[0].get_rdata(), dns.answers[0].get_rdata());
+        assert_eq!(copy.authorities[0].get_ttl(), &0);
+        assert_eq!(copy.additionals, dns.additionals);
+    }
+
+    #[test]
+    fn test_ttl_limits() {
+        let domain_name = DomainName::try_from("example.com").unwrap();
+        let rdata = RData::A(Ipv4Addr::new(192, 0, 2, 1));
+        let answer = RR::new(domain_name, Class::IN, 7200, rdata);
+        let ttl_limits = create_ttl_limits(false);
+        let duration = Duration::from_secs(7200);
+
+        let dns = create_response(RCode::NoError, vec![answer]);
+        assert!(!is_negative(&dns));
+        assert_eq!(ttl_limits.limit(&dns, duration), Duration::from_secs(3600));
+        assert_eq!(
+            ttl_limits.limit(&dns, Duration::from_secs(0)),
+            Duration::from_secs(60)
+        );
+
+        let dns = create_response(RCode::NoError, Vec::new());
+        assert!(is_negative(&dns));
+        assert_eq!(ttl_limits.limit(&dns, duration), Duration::from_secs(900));
+
+        let dns = create_response(RCode::NXDomain, Vec::new());
+        assert!(is_negative(&dns));
+        assert_eq!(ttl_limits.limit(&dns, duration), Duration::from_secs(900));
+
+        let dns = create_response(RCode::ServFail, Vec::new());
+        assert!(!is_negative(&dns));
+        assert_eq!(ttl_limits.limit(&dns, duration), SERVFAIL_TTL);
+        assert_eq!(
+            ttl_limits.limit(&dns, Duration::from_secs(0)),
+            Duration::from_secs(0)
+        );
+    }
+
+    #[test]
+    fn test_ttl_limits_rewrite() {
+        let domain_name = DomainName::try_from("example.com").unwrap();
+        let rdata = RData::A(Ipv4Addr::new(192, 0, 2, 1));
+        let answers = vec![
+            RR::new(domain_name.clone(), Class::IN, 7200, rdata.clone()),
+            RR::new(domain_name.clone(), Class::IN, 5, rdata.clone()),
+            RR::new(domain_name, Class::IN, 300, rdata),
+        ];
+
+        let dns = create_response(RCode::NoError, answers);
+        let dns = create_ttl_limits(false).rewrite(dns);
+        let ttls: Vec<u32> = dns.answers.iter().map(|rr| *rr.get_ttl()).collect();
+        assert_eq!(ttls, vec![7200, 5, 300]);
+
+        let dns = create_ttl_limits(true).rewrite(dns);
+        let ttls: Vec<u32> = dns.answers.iter().map(|rr| *rr.get_ttl()).collect();
+        assert_eq!(ttls, vec![3600, 60, 300]);
+    }
+}